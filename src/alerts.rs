@@ -0,0 +1,207 @@
+use actix_web::rt::time::interval;
+use actix_web::web;
+use log::{error, info, warn};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::cache::TransactionCache;
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules, Settings};
+use crate::db::Database;
+use crate::money::format_base;
+use crate::up::{Timeouts, UpClient};
+use crate::{
+    apply_income_allocations, apply_prior_rollover, categorize, current_month_range,
+    current_period, exclude_held, fetch_range, percent_used, BudgetCategory, Sources,
+};
+
+// Where and when to send budget alerts, from `ALERT_WEBHOOK_URL`,
+// `ALERT_THRESHOLDS` and `ALERT_INTERVAL_MINS`.
+#[derive(Clone)]
+pub struct AlertConfig {
+    client: reqwest::Client,
+    webhook_url: String,
+    // Percentages of a category's allocation, ascending
+    thresholds: Vec<f64>,
+    interval: Duration,
+}
+
+// Reads the alert settings. Alerts are off, `None`, without a webhook URL.
+// Webhooks get the same time limits as Up Bank requests.
+pub fn load_alert_config(settings: &mut Settings, timeouts: Timeouts) -> Option<AlertConfig> {
+    let webhook_url = settings.get("ALERT_WEBHOOK_URL")?;
+    if !webhook_url.starts_with("https://") && !webhook_url.starts_with("http://") {
+        settings.error(format!(
+            "ALERT_WEBHOOK_URL must be an http(s) URL, got \"{}\"",
+            webhook_url
+        ));
+    }
+
+    let raw_thresholds = settings
+        .get("ALERT_THRESHOLDS")
+        .unwrap_or_else(|| "90".to_string());
+    let mut thresholds = Vec::new();
+    for threshold in raw_thresholds.split(',').map(str::trim) {
+        match threshold.trim_end_matches('%').parse::<f64>() {
+            Ok(percent) if percent > 0.0 => thresholds.push(percent),
+            _ => {
+                settings.error(format!(
+                    "ALERT_THRESHOLDS must be comma-separated positive percentages like \"90,100\", got \"{}\"",
+                    raw_thresholds
+                ));
+                break;
+            }
+        }
+    }
+    thresholds.sort_by(f64::total_cmp);
+    thresholds.dedup();
+
+    let minutes: u64 = settings.parse_where(
+        "ALERT_INTERVAL_MINS",
+        15,
+        |minutes| *minutes > 0,
+        "a whole number of minutes, at least 1",
+    );
+
+    let client = reqwest::Client::builder()
+        .timeout(timeouts.request)
+        .connect_timeout(timeouts.connect)
+        .build()
+        .unwrap_or_else(|e| {
+            settings.error(format!("failed to set up the alert webhook client: {}", e));
+            reqwest::Client::new()
+        });
+
+    Some(AlertConfig {
+        client,
+        webhook_url,
+        thresholds,
+        interval: Duration::from_secs(minutes * 60),
+    })
+}
+
+// The highest threshold `category` has reached, if any.
+fn crossed_threshold(category: &BudgetCategory, thresholds: &[f64]) -> Option<f64> {
+    if category.allocated_amount <= 0 {
+        return None;
+    }
+    let percent = percent_used(category);
+    thresholds
+        .iter()
+        .copied()
+        .rfind(|threshold| percent >= *threshold)
+}
+
+// Discord reads a `content` field and Slack a `text` one.
+fn webhook_body(url: &str, message: &str) -> serde_json::Value {
+    if url.contains("discord.com") || url.contains("discordapp.com") {
+        json!({ "content": message })
+    } else {
+        json!({ "text": message })
+    }
+}
+
+async fn notify(client: &reqwest::Client, url: &str, message: &str) -> Result<(), String> {
+    let response = client
+        .post(url)
+        .json(&webhook_body(url, message))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook responded with {}", response.status()))
+    }
+}
+
+// Works out the current month's budget the same way the budget page does and
+// posts an alert for each category that has newly reached a threshold. Each
+// threshold is only alerted once per category per month, as recorded in the
+// database, so restarts and repeated checks don't repeat alerts.
+async fn check_budget(alerts: &AlertConfig, sources: Sources<'_>, categories: &BudgetConfig) {
+    let Sources { db, config, .. } = sources;
+    let (year, month) = current_period(config.fiscal_start_day);
+    let (since, until) = current_month_range(config.fiscal_start_day);
+    let mut transactions = match fetch_range(sources, since, until, false).await {
+        Ok(transactions) => transactions,
+        Err(e) => {
+            warn!("budget alerts: failed to fetch transactions: {}", e);
+            return;
+        }
+    };
+    exclude_held(&mut transactions, false);
+    let mut budget = categorize(
+        &transactions,
+        &categories.resolve_allocations(year, month),
+        sources.rules,
+    );
+    apply_income_allocations(&mut budget);
+    apply_prior_rollover(&mut budget, db, categories.rollover(), year, month);
+
+    for category in &budget {
+        let Some(threshold) = crossed_threshold(category, &alerts.thresholds) else {
+            continue;
+        };
+        match db.alert_sent(year, month, &category.name, threshold) {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                warn!("budget alerts: failed to check sent alerts: {}", e);
+                continue;
+            }
+        }
+
+        let message = format!(
+            "{} has used {:.0}% of its budget this month: {} of {} spent.",
+            category.name,
+            percent_used(category),
+            format_base(category.spent_amount, &config.base_currency),
+            format_base(category.allocated_amount, &config.base_currency)
+        );
+        match notify(&alerts.client, &alerts.webhook_url, &message).await {
+            Ok(()) => {
+                info!("budget alert sent for {} at {}%", category.name, threshold);
+                // Lower thresholds passed at the same time count as sent too
+                for passed in alerts.thresholds.iter().filter(|t| **t <= threshold) {
+                    if let Err(e) = db.mark_alert_sent(year, month, &category.name, *passed) {
+                        warn!("budget alerts: failed to record sent alert: {}", e);
+                    }
+                }
+            }
+            Err(e) => error!("budget alerts: failed to notify: {}", e),
+        }
+    }
+}
+
+// Checks the budget against the alert thresholds every `interval`, for as
+// long as the server runs.
+pub fn spawn_alerts(
+    alerts: AlertConfig,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    db: web::Data<Database>,
+    rates: web::Data<CurrencyRates>,
+    config: web::Data<Config>,
+    (categories, rules): (web::Data<BudgetConfig>, web::Data<Rules>),
+) {
+    info!(
+        "budget alerts enabled at {:?}% every {} minutes",
+        alerts.thresholds,
+        alerts.interval.as_secs() / 60
+    );
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(alerts.interval);
+        loop {
+            ticks.tick().await;
+            let sources = Sources {
+                up: &up,
+                cache: &cache,
+                db: &db,
+                rates: &rates,
+                config: &config,
+                rules: &rules,
+            };
+            check_budget(&alerts, sources, &categories).await;
+        }
+    });
+}
@@ -0,0 +1,516 @@
+use actix_web::error::QueryPayloadError;
+use actix_web::http::header::{HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Months, Utc};
+use log::warn;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cache::{AccountCache, CacheKey, TransactionCache};
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::money::to_minor_units;
+use crate::rules::{rules_overview, RulesQuery};
+use crate::up::{UpClient, UpError};
+use crate::{
+    apply_income_allocations, apply_prior_rollover, categorize_transactions, current_month_range,
+    current_period, exclude_held, fetch_month, fetch_range, fetch_transactions, filter_by_account,
+    live_accounts, previous_month, query_flag, record_budget, selected_account, selected_month,
+    BudgetQuery, Sources, Transaction,
+};
+
+// How far back a single `/sync` may reach, and how long a `/transactions`
+// range may be, to keep either within Up Bank's rate limits.
+const MAX_SYNC_MONTHS: u32 = 24;
+
+#[derive(Deserialize)]
+pub struct TransactionsQuery {
+    since: Option<String>,
+    until: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+    category: Option<String>,
+    refresh: Option<String>,
+}
+
+fn parse_timestamp(name: &str, value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| format!("{} must be an RFC 3339 timestamp, got \"{}\"", name, value))
+}
+
+// Errors from JSON endpoints, rendered as
+// `{"error": {"code": "...", "message": "..."}}` with a matching status so
+// clients can branch on `code` rather than parsing messages.
+#[derive(Debug)]
+pub enum ApiError {
+    // The request itself is malformed or out of range
+    BadRequest(String),
+    Unauthorized(String),
+    // A feature the request needs isn't configured on this server
+    Unavailable(String),
+    // The request ran past `REQUEST_TIMEOUT_SECS`
+    Timeout(String),
+    Upstream(UpError),
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Timeout(_) => "timeout",
+            ApiError::Upstream(e) if e.rate_limit().is_some() => "rate_limited",
+            ApiError::Upstream(e) if e.is_timeout() => "upstream_timeout",
+            ApiError::Upstream(e) if e.is_unreachable() => "upstream_unavailable",
+            ApiError::Upstream(e) if e.is_unauthorized() => "upstream_unauthorized",
+            ApiError::Upstream(_) => "upstream_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Timeout(message) => f.write_str(message),
+            ApiError::Upstream(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<UpError> for ApiError {
+    fn from(e: UpError) -> Self {
+        ApiError::Upstream(e)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Upstream(e) if e.rate_limit().is_some() => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream(e) if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Upstream(e) if e.is_unreachable() => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code()).json(json!({
+            "error": { "code": self.code(), "message": self.to_string() }
+        }));
+        if let ApiError::Upstream(e) = self {
+            if let Some(Some(delay)) = e.rate_limit() {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(delay.as_secs()));
+            }
+        }
+        response
+    }
+}
+
+// Reports malformed query strings on `/api` routes as JSON rather than
+// actix's default plain-text body.
+pub fn query_error(err: QueryPayloadError, _req: &HttpRequest) -> Error {
+    ApiError::BadRequest(err.to_string()).into()
+}
+
+pub async fn budget(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, ApiError> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month) =
+        selected_month(&query, config.fiscal_start_day).map_err(ApiError::BadRequest)?;
+
+    let key = CacheKey {
+        account_id: None,
+        year,
+        month,
+    };
+
+    let mut transactions = fetch_transactions(sources, key, query_flag(&query.refresh)).await?;
+    filter_by_account(&mut transactions, selected_account(&query.account_id));
+    exclude_held(&mut transactions, query_flag(&query.include_held));
+    let budget_categories = categories.resolve_allocations(year, month);
+    let mut categorized = categorize_transactions(transactions, budget_categories, &rules);
+    apply_income_allocations(&mut categorized);
+    // Same as `/budget`: rollover and the recorded snapshot only cover the
+    // whole budget
+    if selected_account(&query.account_id).is_none() {
+        apply_prior_rollover(&mut categorized, &db, categories.rollover(), year, month);
+        record_budget(&db, year, month, &categorized);
+    }
+    Ok(HttpResponse::Ok().json(categorized))
+}
+
+// The `since..until` range asked for, defaulting to the current budget period
+// so far. Every month in between is fetched, so the range may not end in the
+// future or span more than `MAX_SYNC_MONTHS` months.
+fn requested_range(
+    query: &TransactionsQuery,
+    start_day: u32,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let now = Utc::now();
+    let since = match &query.since {
+        Some(since) => parse_timestamp("since", since)?,
+        None => current_month_range(start_day).0,
+    };
+    let until = match &query.until {
+        Some(until) => parse_timestamp("until", until)?,
+        None => now,
+    };
+    if since >= until {
+        return Err("since must be before until".to_string());
+    }
+    if until > now {
+        return Err("until must not be in the future".to_string());
+    }
+    if since
+        .checked_add_months(Months::new(MAX_SYNC_MONTHS))
+        .is_some_and(|limit| until > limit)
+    {
+        return Err(format!(
+            "since..until may span at most {} months",
+            MAX_SYNC_MONTHS
+        ));
+    }
+    Ok((since, until))
+}
+
+// Transactions created in `since..until`, defaulting to the current month so
+// far, optionally narrowed by amount (in the base currency) and category.
+pub async fn transactions(
+    query: web::Query<TransactionsQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, ApiError> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (since, until) =
+        requested_range(&query, config.fiscal_start_day).map_err(ApiError::BadRequest)?;
+    if let (Some(min), Some(max)) = (query.min, query.max) {
+        if min > max {
+            return Err(ApiError::BadRequest(
+                "min must not be greater than max".to_string(),
+            ));
+        }
+    }
+
+    let refresh = query_flag(&query.refresh);
+    let mut transactions = fetch_range(sources, since, until, refresh).await?;
+
+    // `min` and `max` are whole units, compared at the precision amounts
+    // are kept in
+    let base = &config.base_currency;
+    let min = query.min.map(|min| to_minor_units(min, base));
+    let max = query.max.map(|max| to_minor_units(max, base));
+    transactions.retain(|transaction| {
+        let amount = transaction.base_amount();
+        min.is_none_or(|min| amount >= min) && max.is_none_or(|max| amount <= max)
+    });
+
+    if let Some(category) = &query.category {
+        let categorized =
+            categorize_transactions(transactions, categories.categories().to_vec(), &rules);
+        transactions = categorized
+            .into_iter()
+            .find(|c| c.name.eq_ignore_ascii_case(category))
+            .map(|c| c.transactions)
+            .unwrap_or_default();
+    }
+
+    transactions.sort_by_key(|transaction| {
+        std::cmp::Reverse(DateTime::parse_from_rfc3339(&transaction.date).ok())
+    });
+    Ok(HttpResponse::Ok().json(transactions))
+}
+
+pub async fn accounts(
+    up: web::Data<UpClient>,
+    account_cache: web::Data<AccountCache>,
+) -> Result<HttpResponse, ApiError> {
+    let accounts: Vec<_> = live_accounts(&up, &account_cache)
+        .await?
+        .iter()
+        .map(|account| {
+            json!({
+                "id": account.id,
+                "display_name": account.attributes.display_name,
+                "balance": account.attributes.balance.amount(),
+                "currency": account.attributes.balance.currency_code,
+                "account_type": account.attributes.account_type,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(accounts))
+}
+
+#[derive(Deserialize)]
+struct SyncRequest {
+    // Budget periods to resync, counting back from and including the current
+    // one
+    #[serde(default = "default_sync_months")]
+    months: u32,
+}
+
+fn default_sync_months() -> u32 {
+    1
+}
+
+// Compared field by field rather than with `PartialEq`, since stored
+// transactions haven't been currency converted yet.
+fn transaction_changed(stored: &Transaction, fetched: &Transaction) -> bool {
+    stored.status != fetched.status
+        || stored.amount != fetched.amount
+        || stored.currency != fetched.currency
+        || stored.description != fetched.description
+        || stored.settled_at != fetched.settled_at
+        || stored.round_up != fetched.round_up
+        || stored.tags != fetched.tags
+        || stored.is_transfer != fetched.is_transfer
+        || stored.up_category != fetched.up_category
+        || stored.attachment_id != fetched.attachment_id
+}
+
+// `POST /sync`: throws away cached transactions and fetches them again from
+// Up Bank, for picking up changes made in the Up app straight away. Resyncs
+// the current period, or the last `months` periods given a JSON body like
+// `{"months": 3}`, and reports how many stored transactions were added,
+// updated or removed.
+pub async fn sync(
+    body: web::Bytes,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, ApiError> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let request = if body.iter().all(u8::is_ascii_whitespace) {
+        SyncRequest {
+            months: default_sync_months(),
+        }
+    } else {
+        serde_json::from_slice::<SyncRequest>(&body)
+            .map_err(|e| ApiError::BadRequest(format!("invalid sync request: {}", e)))?
+    };
+    if !(1..=MAX_SYNC_MONTHS).contains(&request.months) {
+        return Err(ApiError::BadRequest(format!(
+            "months must be between 1 and {}",
+            MAX_SYNC_MONTHS
+        )));
+    }
+
+    let (mut year, mut month) = current_period(config.fiscal_start_day);
+    let mut periods = Vec::new();
+    let (mut total_added, mut total_updated, mut total_removed) = (0, 0, 0);
+    for _ in 0..request.months {
+        let stored = db.load_transactions(year, month).unwrap_or_else(|e| {
+            warn!("failed to load stored transactions: {}", e);
+            Vec::new()
+        });
+        cache.clear_month(year, month);
+        let key = CacheKey {
+            account_id: None,
+            year,
+            month,
+        };
+        let fetched = fetch_month(sources, key, true).await?;
+
+        let stored_by_id: HashMap<&str, &Transaction> = stored
+            .iter()
+            .map(|transaction| (transaction.id.as_str(), transaction))
+            .collect();
+        let mut added = 0;
+        let mut updated = 0;
+        for transaction in &fetched {
+            match stored_by_id.get(transaction.id.as_str()) {
+                None => added += 1,
+                Some(existing) if transaction_changed(existing, transaction) => updated += 1,
+                Some(_) => {}
+            }
+        }
+        // Anything stored but no longer listed by Up Bank, such as a declined
+        // hold, is gone for good
+        let mut removed = 0;
+        for transaction in &stored {
+            if fetched.iter().any(|t| t.id == transaction.id) {
+                continue;
+            }
+            match db.delete_transaction(&transaction.id) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("failed to delete transaction {}: {}", transaction.id, e),
+            }
+        }
+
+        periods.push(json!({
+            "year": year,
+            "month": month,
+            "transactions": fetched.len(),
+            "added": added,
+            "updated": updated,
+            "removed": removed,
+        }));
+        total_added += added;
+        total_updated += updated;
+        total_removed += removed;
+        (year, month) = previous_month(year, month);
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "added": total_added,
+        "updated": total_updated,
+        "removed": total_removed,
+        "periods": periods,
+    })))
+}
+
+// Up Bank's categories and the budget category each is mapped to, if any, for
+// filling in `[up_categories]` in the rules file.
+pub async fn up_categories(
+    up: web::Data<UpClient>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, ApiError> {
+    let categories: Vec<_> = up
+        .list_categories()
+        .await?
+        .into_iter()
+        .map(|category| {
+            let budget_category = rules.up_categories.get(&category.id);
+            json!({
+                "id": category.id,
+                "name": category.name,
+                "parent": category.parent,
+                "budget_category": budget_category,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+// The categorization rules, and where `?description=` would land, as on
+// `/rules`.
+pub async fn rules(
+    query: web::Query<RulesQuery>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(rules_overview(&query, &categories, &rules))
+}
+
+// Checks the configured API key against Up Bank, for confirming setup.
+pub async fn ping(up: web::Data<UpClient>) -> Result<HttpResponse, ApiError> {
+    match up.ping().await {
+        Ok(emoji) => Ok(HttpResponse::Ok().json(json!({ "ok": true, "emoji": emoji }))),
+        Err(e) if e.is_unauthorized() => Err(ApiError::Unauthorized(
+            "Up Bank rejected the configured API_KEY; check it is a current personal access token"
+                .to_string(),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Liveness probe; deliberately never touches Up Bank.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+// Readiness probe; checks that Up Bank is reachable and accepts the API key.
+pub async fn readyz(up: web::Data<UpClient>) -> HttpResponse {
+    match up.ping().await {
+        Ok(_) => HttpResponse::Ok().json(json!({ "status": "ok" })),
+        Err(e) => HttpResponse::ServiceUnavailable()
+            .json(json!({ "status": "unavailable", "error": e.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range_query(since: Option<&str>, until: Option<&str>) -> TransactionsQuery {
+        TransactionsQuery {
+            since: since.map(str::to_string),
+            until: until.map(str::to_string),
+            min: None,
+            max: None,
+            category: None,
+            refresh: None,
+        }
+    }
+
+    #[test]
+    fn requested_range_accepts_a_past_range() {
+        let query = range_query(Some("2024-01-01T00:00:00Z"), Some("2024-03-01T00:00:00Z"));
+        let (since, until) = requested_range(&query, 1).unwrap();
+        assert_eq!(since.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!(until.to_rfc3339(), "2024-03-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn requested_range_rejects_an_empty_or_backwards_range() {
+        let same = range_query(Some("2024-03-01T00:00:00Z"), Some("2024-03-01T00:00:00Z"));
+        assert!(requested_range(&same, 1).is_err());
+        let backwards = range_query(Some("2024-03-01T00:00:00Z"), Some("2024-01-01T00:00:00Z"));
+        assert!(requested_range(&backwards, 1).is_err());
+    }
+
+    #[test]
+    fn requested_range_rejects_a_future_until() {
+        let until = (Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+        let query = range_query(None, Some(&until));
+        assert!(requested_range(&query, 1).is_err());
+    }
+
+    #[test]
+    fn requested_range_rejects_a_span_over_the_limit() {
+        let query = range_query(Some("1970-01-01T00:00:00Z"), Some("2024-01-01T00:00:00Z"));
+        assert!(requested_range(&query, 1).is_err());
+        let longest = range_query(Some("2022-01-01T00:00:00Z"), Some("2024-01-01T00:00:00Z"));
+        assert!(requested_range(&longest, 1).is_ok());
+        let too_long = range_query(Some("2022-01-01T00:00:00Z"), Some("2024-01-01T00:00:01Z"));
+        assert!(requested_range(&too_long, 1).is_err());
+    }
+}
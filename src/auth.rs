@@ -0,0 +1,134 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse, ResponseError};
+use askama::Template;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::api::ApiError;
+use crate::config::Config;
+use crate::render_template;
+use crate::webhook::verify_signature;
+
+const SESSION_COOKIE: &str = "session";
+const SESSION_DAYS: i64 = 30;
+
+// Paths reachable without signing in. The webhook authenticates itself with
+// its own signature.
+const PUBLIC_PATHS: &[&str] = &["/healthz", "/readyz", "/login", "/webhook"];
+
+// A session cookie is `<expiry>.<hex HMAC of expiry>`, keyed by the app token
+// so changing the token signs everyone out.
+fn session_value(token: &str, expires: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(expires.to_string().as_bytes());
+    format!("{}.{}", expires, hex::encode(mac.finalize().into_bytes()))
+}
+
+fn valid_session(token: &str, value: &str) -> bool {
+    let Some((expires, signature)) = value.split_once('.') else {
+        return false;
+    };
+    let Ok(expires_at) = expires.parse::<i64>() else {
+        return false;
+    };
+    expires_at > Utc::now().timestamp() && verify_signature(token, expires.as_bytes(), signature)
+}
+
+fn is_authenticated(req: &ServiceRequest, token: &str) -> bool {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|candidate| bool::from(token.as_bytes().ct_eq(candidate.trim().as_bytes())));
+
+    bearer
+        || req
+            .cookie(SESSION_COOKIE)
+            .is_some_and(|cookie| valid_session(token, cookie.value()))
+}
+
+// Requires `Authorization: Bearer <APP_TOKEN>` or a session cookie from the
+// login form, when `APP_TOKEN` is set. Pages redirect to the login form;
+// `/api` routes and `/sync` answer 401 JSON.
+pub async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let token = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.app_token.clone());
+
+    let allowed = match &token {
+        None => true,
+        Some(token) => PUBLIC_PATHS.contains(&req.path()) || is_authenticated(&req, token),
+    };
+    if allowed {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let response = if req.path().starts_with("/api/") || req.path() == "/sync" {
+        ApiError::Unauthorized("authentication required".to_string()).error_response()
+    } else {
+        let mut response = HttpResponse::SeeOther().finish();
+        response
+            .headers_mut()
+            .insert(header::LOCATION, HeaderValue::from_static("/login"));
+        response
+    };
+    Ok(req.into_response(response).map_into_right_body())
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    failed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    token: String,
+}
+
+pub async fn login_page(config: web::Data<Config>) -> HttpResponse {
+    render_template(&LoginTemplate { failed: false }, &config)
+}
+
+pub async fn login(form: web::Form<LoginForm>, config: web::Data<Config>) -> HttpResponse {
+    let Some(token) = config.app_token.as_deref() else {
+        return HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, "/"))
+            .finish();
+    };
+
+    if !bool::from(token.as_bytes().ct_eq(form.token.trim().as_bytes())) {
+        let mut response = render_template(&LoginTemplate { failed: true }, &config);
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        return response;
+    }
+
+    let expires = Utc::now().timestamp() + SESSION_DAYS * 24 * 60 * 60;
+    let cookie = Cookie::build(SESSION_COOKIE, session_value(token, expires))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(actix_web::cookie::time::Duration::days(SESSION_DAYS))
+        .finish();
+
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, "/"))
+        .cookie(cookie)
+        .finish()
+}
@@ -0,0 +1,282 @@
+use chrono::{DateTime, Utc};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::metrics::METRICS;
+use crate::up::Account;
+use crate::{period_of, Transaction};
+
+// Identifies one month of transactions, optionally narrowed to a single
+// account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub account_id: Option<String>,
+    pub year: i32,
+    pub month: u32,
+}
+
+struct CacheEntry {
+    fetched_at: Instant,
+    transactions: Vec<Transaction>,
+}
+
+// Everything behind the cache's lock, so a reader never sees an entry
+// without the changes counted against its month.
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    // Bumped whenever a webhook changes a month's transactions, so listings
+    // fetched before the change can be told apart from ones fetched after
+    changes: HashMap<(i32, u32), u64>,
+    // Deletions don't say which month they belong to, so they count against
+    // every month
+    removals: u64,
+}
+
+impl CacheState {
+    fn generation(&self, year: i32, month: u32) -> u64 {
+        self.removals + self.changes.get(&(year, month)).copied().unwrap_or(0)
+    }
+}
+
+// In-memory cache of fetched transactions, shared between workers.
+pub struct TransactionCache {
+    ttl: Duration,
+    // Day of the month budget periods start on, for placing upserts
+    start_day: u32,
+    state: RwLock<CacheState>,
+}
+
+impl TransactionCache {
+    pub fn new(ttl: Duration, start_day: u32) -> Self {
+        TransactionCache {
+            ttl,
+            start_day,
+            state: RwLock::new(CacheState::default()),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<Transaction>> {
+        let state = self.state.read().unwrap_or_else(PoisonError::into_inner);
+        let transactions = state
+            .entries
+            .get(key)
+            .filter(|entry| entry.fetched_at.elapsed() < self.ttl)
+            .map(|entry| entry.transactions.clone());
+        METRICS.record_cache_lookup(transactions.is_some());
+        transactions
+    }
+
+    // A marker for the month's state, to take before fetching it and hand
+    // back to `insert`.
+    pub fn generation(&self, year: i32, month: u32) -> u64 {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .generation(year, month)
+    }
+
+    // Caches a listing fetched since `generation` was taken. If a webhook
+    // changed the month in the meantime the listing may already be stale,
+    // so it is left out and the next request fetches again.
+    pub fn insert(&self, key: CacheKey, generation: u64, transactions: Vec<Transaction>) {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        if state.generation(key.year, key.month) != generation {
+            debug!(
+                "not caching {}/{}: changed while it was being fetched",
+                key.month, key.year
+            );
+            return;
+        }
+        // Drop anything that has expired so the map doesn't grow without bound
+        state
+            .entries
+            .retain(|_, entry| entry.fetched_at.elapsed() < self.ttl);
+        state.entries.insert(
+            key,
+            CacheEntry {
+                fetched_at: Instant::now(),
+                transactions,
+            },
+        );
+    }
+
+    // Folds a new or updated transaction into every cached listing that
+    // covers it, so a webhook doesn't have to throw away a whole month. Only
+    // listings for its month and for its account, or for all accounts, are
+    // touched.
+    pub fn upsert(&self, transaction: &Transaction) {
+        let Ok(date) = DateTime::parse_from_rfc3339(&transaction.date) else {
+            return;
+        };
+        // Periods are bounded in UTC, matching the ranges fetched from Up Bank
+        let (year, month) = period_of(self.start_day, date.with_timezone(&Utc));
+
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        *state.changes.entry((year, month)).or_default() += 1;
+        for (key, entry) in state.entries.iter_mut() {
+            let covers = key.year == year
+                && key.month == month
+                && key
+                    .account_id
+                    .as_deref()
+                    .is_none_or(|id| id == transaction.account_id);
+            if !covers {
+                continue;
+            }
+            match entry
+                .transactions
+                .iter_mut()
+                .find(|t| t.id == transaction.id)
+            {
+                Some(existing) => *existing = transaction.clone(),
+                None => entry.transactions.insert(0, transaction.clone()),
+            }
+        }
+    }
+
+    // Drops every listing for a month, whichever account it covers.
+    pub fn clear_month(&self, year: i32, month: u32) {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        *state.changes.entry((year, month)).or_default() += 1;
+        state
+            .entries
+            .retain(|key, _| key.year != year || key.month != month);
+    }
+
+    pub fn remove(&self, id: &str) {
+        let mut state = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        state.removals += 1;
+        for entry in state.entries.values_mut() {
+            entry.transactions.retain(|t| t.id != id);
+        }
+    }
+}
+
+// The parts of an account that rarely change. Balances are deliberately left
+// out so they are always fetched live.
+#[derive(Debug, Clone)]
+pub struct AccountInfo {
+    pub id: String,
+    pub display_name: String,
+}
+
+impl From<&Account> for AccountInfo {
+    fn from(account: &Account) -> Self {
+        AccountInfo {
+            id: account.id.clone(),
+            display_name: account.attributes.display_name.clone(),
+        }
+    }
+}
+
+// In-memory cache of the account list, kept much longer than transactions
+// since accounts are rarely opened or renamed.
+pub struct AccountCache {
+    ttl: Duration,
+    entry: RwLock<Option<(Instant, Vec<AccountInfo>)>>,
+}
+
+impl AccountCache {
+    pub fn new(ttl: Duration) -> Self {
+        AccountCache {
+            ttl,
+            entry: RwLock::new(None),
+        }
+    }
+
+    pub fn get(&self) -> Option<Vec<AccountInfo>> {
+        let entry = self.entry.read().unwrap_or_else(PoisonError::into_inner);
+        entry
+            .as_ref()
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.ttl)
+            .map(|(_, accounts)| accounts.clone())
+    }
+
+    pub fn insert(&self, accounts: &[Account]) {
+        let accounts = accounts.iter().map(AccountInfo::from).collect();
+        *self.entry.write().unwrap_or_else(PoisonError::into_inner) =
+            Some((Instant::now(), accounts));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::transaction;
+    use std::sync::Barrier;
+
+    fn march() -> CacheKey {
+        CacheKey {
+            account_id: None,
+            year: 2024,
+            month: 3,
+        }
+    }
+
+    #[test]
+    fn insert_caches_an_unchanged_listing() {
+        let cache = TransactionCache::new(Duration::from_secs(60), 1);
+        let generation = cache.generation(2024, 3);
+        cache.insert(march(), generation, vec![transaction("a", "Coles", -1000)]);
+        assert_eq!(cache.get(&march()).map(|t| t.len()), Some(1));
+    }
+
+    #[test]
+    fn insert_skips_a_listing_fetched_before_an_upsert() {
+        let cache = TransactionCache::new(Duration::from_secs(60), 1);
+        let generation = cache.generation(2024, 3);
+        // A webhook lands for March while its listing is being fetched
+        cache.upsert(&transaction("b", "Aldi", -500));
+        cache.insert(march(), generation, vec![transaction("a", "Coles", -1000)]);
+        assert!(cache.get(&march()).is_none());
+    }
+
+    #[test]
+    fn insert_ignores_upserts_for_other_months() {
+        let cache = TransactionCache::new(Duration::from_secs(60), 1);
+        let generation = cache.generation(2024, 3);
+        let mut april = transaction("b", "Aldi", -500);
+        april.date = "2024-04-10T10:00:00+10:00".to_string();
+        cache.upsert(&april);
+        cache.insert(march(), generation, vec![transaction("a", "Coles", -1000)]);
+        assert!(cache.get(&march()).is_some());
+    }
+
+    #[test]
+    fn insert_skips_a_listing_fetched_before_a_removal() {
+        let cache = TransactionCache::new(Duration::from_secs(60), 1);
+        let generation = cache.generation(2024, 3);
+        cache.remove("a");
+        cache.insert(march(), generation, vec![transaction("a", "Coles", -1000)]);
+        assert!(cache.get(&march()).is_none());
+    }
+
+    #[test]
+    fn racing_webhook_never_leaves_a_stale_listing() {
+        for _ in 0..200 {
+            let cache = TransactionCache::new(Duration::from_secs(60), 1);
+            let barrier = Barrier::new(2);
+            std::thread::scope(|scope| {
+                // A request starts fetching March, then a webhook for March
+                // lands while the listing is in flight
+                scope.spawn(|| {
+                    let generation = cache.generation(2024, 3);
+                    barrier.wait();
+                    cache.insert(march(), generation, vec![transaction("a", "Coles", -1000)]);
+                });
+                scope.spawn(|| {
+                    barrier.wait();
+                    cache.upsert(&transaction("b", "Aldi", -500));
+                });
+            });
+            // Cached first and then updated, or never cached: either way
+            // the webhook's transaction is not lost
+            if let Some(listing) = cache.get(&march()) {
+                assert!(listing.iter().any(|t| t.id == "b"));
+            }
+        }
+    }
+}
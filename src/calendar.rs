@@ -0,0 +1,168 @@
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::{DateTime, Datelike, NaiveDate};
+use chrono_tz::Tz;
+
+use crate::cache::TransactionCache;
+use crate::config::{Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::export::selected_transactions;
+use crate::money::filters;
+use crate::up::UpClient;
+use crate::{
+    exclude_transfers, next_month, period_start, previous_month, render_template, BudgetQuery,
+    Sources, Transaction,
+};
+
+// One cell of the calendar grid.
+struct CalendarDay {
+    // `None` for the blank cells padding out the first and last weeks
+    date: Option<NaiveDate>,
+    // In minor units of the base currency
+    spent: i64,
+    count: usize,
+    // Spending relative to the month's biggest day, from 0 to 1
+    intensity: f64,
+}
+
+impl CalendarDay {
+    // Background shading, darker for bigger days. Days without spending are
+    // left unshaded so they stand out as no-spend days.
+    fn style(&self) -> String {
+        if self.spent <= 0 {
+            return String::new();
+        }
+        let alpha = 0.15 + 0.85 * self.intensity;
+        let text = if alpha > 0.55 { "color: #fff;" } else { "" };
+        format!(
+            "background-color: rgba(220, 53, 69, {:.2}); {}",
+            alpha, text
+        )
+    }
+}
+
+#[derive(Template)]
+#[template(path = "calendar.html")]
+struct CalendarTemplate {
+    month_label: String,
+    prev_year: i32,
+    prev_month: u32,
+    next_year: i32,
+    next_month: u32,
+    // Monday-first weeks covering the budget period
+    weeks: Vec<Vec<CalendarDay>>,
+    total_spent: i64,
+}
+
+// Lays the period from `start` up to `end` out as Monday-first weeks, with
+// each day's spending summed from the outgoing transactions in the display
+// timezone `tz`.
+fn calendar_weeks(
+    transactions: &[Transaction],
+    start: NaiveDate,
+    end: NaiveDate,
+    tz: Tz,
+) -> Vec<Vec<CalendarDay>> {
+    let days = (end - start).num_days().max(0) as usize;
+
+    let mut daily = vec![(0, 0); days];
+    for transaction in transactions {
+        let amount = transaction.base_amount();
+        if amount >= 0 {
+            continue;
+        }
+        let Ok(date) = DateTime::parse_from_rfc3339(&transaction.date) else {
+            continue;
+        };
+        let day = (date.with_timezone(&tz).date_naive() - start).num_days();
+        if let Some((spent, count)) = usize::try_from(day).ok().and_then(|day| daily.get_mut(day)) {
+            *spent += amount.abs();
+            *count += 1;
+        }
+    }
+    let max = daily
+        .iter()
+        .map(|(spent, _)| *spent)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+
+    let blank = || CalendarDay {
+        date: None,
+        spent: 0,
+        count: 0,
+        intensity: 0.0,
+    };
+    let mut cells: Vec<CalendarDay> = (0..start.weekday().num_days_from_monday())
+        .map(|_| blank())
+        .collect();
+    cells.extend(
+        start
+            .iter_days()
+            .zip(daily)
+            .map(|(date, (spent, count))| CalendarDay {
+                date: Some(date),
+                spent,
+                count,
+                intensity: spent as f64 / max,
+            }),
+    );
+    while !cells.len().is_multiple_of(7) {
+        cells.push(blank());
+    }
+
+    let mut weeks = Vec::new();
+    let mut cells = cells.into_iter().peekable();
+    while cells.peek().is_some() {
+        weeks.push(cells.by_ref().take(7).collect());
+    }
+    weeks
+}
+
+// `/calendar`: the month's spending by day as a heatmap.
+pub async fn calendar_page(
+    query: web::Query<BudgetQuery>,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, mut transactions) = selected_transactions(&query, sources).await?;
+    exclude_transfers(&mut transactions, false);
+
+    let (prev_year, prev_month) = previous_month(year, month);
+    let (next_year, next_month) = next_month(year, month);
+    let start_day = config.fiscal_start_day;
+    let weeks = calendar_weeks(
+        &transactions,
+        period_start(start_day, year, month),
+        period_start(start_day, next_year, next_month),
+        config.display_tz,
+    );
+    let total_spent = weeks.iter().flatten().map(|day| day.spent).sum();
+
+    Ok(render_template(
+        &CalendarTemplate {
+            month_label: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|date| date.format("%B %Y").to_string())
+                .unwrap_or_else(|| format!("{}/{}", month, year)),
+            prev_year,
+            prev_month,
+            next_year,
+            next_month,
+            weeks,
+            total_spent,
+        },
+        &config,
+    ))
+}
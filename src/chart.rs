@@ -0,0 +1,280 @@
+use chrono::NaiveDate;
+use std::fmt::Write;
+
+use crate::money::{format_base, format_base_whole, from_minor_units};
+use crate::networth::BalanceSnapshot;
+use crate::{escape_html, BudgetCategory};
+
+// Layout of the budget chart, in SVG user units.
+const PLOT_HEIGHT: f64 = 200.0;
+const GROUP_WIDTH: f64 = 56.0;
+const BAR_WIDTH: f64 = 20.0;
+const MARGIN_LEFT: f64 = 64.0;
+const MARGIN_RIGHT: f64 = 16.0;
+const MARGIN_TOP: f64 = 28.0;
+// Room under the plot for the slanted category names
+const MARGIN_BOTTOM: f64 = 84.0;
+// Category names longer than this are shortened; the full name is kept in a
+// tooltip
+const MAX_LABEL_CHARS: usize = 14;
+const Y_TICKS: usize = 4;
+
+const ALLOCATED_COLOR: &str = "#adb5bd";
+const SPENT_COLOR: &str = "#007bff";
+const OVERSPENT_COLOR: &str = "#dc3545";
+
+// A round step for axis ticks so the scale covers `max` in about `Y_TICKS`
+// steps, e.g. 0, 250, 500, 750, 1000.
+fn tick_step(max: f64) -> f64 {
+    let raw = max / Y_TICKS as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 2.5, 5.0, 10.0]
+        .iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude);
+    step.max(1.0)
+}
+
+fn short_label(name: &str) -> String {
+    if name.chars().count() <= MAX_LABEL_CHARS {
+        name.to_string()
+    } else {
+        let short: String = name.chars().take(MAX_LABEL_CHARS - 1).collect();
+        format!("{}…", short.trim_end())
+    }
+}
+
+// Grouped bars of allocated against spent for each category, as inline SVG.
+// Spending over the allocation is drawn in red. A category with nothing
+// allocated gets a flat marker on the axis rather than no bar at all. Amounts
+// are in the base currency `base`. Empty when there are no categories.
+pub fn budget_chart(categories: &[BudgetCategory], base: &str) -> String {
+    if categories.is_empty() {
+        return String::new();
+    }
+
+    let max = categories
+        .iter()
+        .flat_map(|category| [category.allocated_amount, category.spent_amount])
+        .map(|amount| from_minor_units(amount, base))
+        .fold(0.0, f64::max);
+    let step = tick_step(max);
+    let scale_max = (max / step).ceil().max(1.0) * step;
+    let y = |amount: f64| MARGIN_TOP + PLOT_HEIGHT - amount.max(0.0) / scale_max * PLOT_HEIGHT;
+
+    let plot_width = categories.len() as f64 * GROUP_WIDTH;
+    let width = MARGIN_LEFT + plot_width + MARGIN_RIGHT;
+    let height = MARGIN_TOP + PLOT_HEIGHT + MARGIN_BOTTOM;
+    let axis_y = MARGIN_TOP + PLOT_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<svg viewBox=\"0 0 {width:.0} {height:.0}\" width=\"100%\" style=\"max-width: {width:.0}px\" \
+         role=\"img\" aria-label=\"Allocated and spent amount for each category\" font-size=\"11\">"
+    );
+
+    // Legend
+    let _ = write!(
+        svg,
+        "<rect x=\"{MARGIN_LEFT}\" y=\"6\" width=\"10\" height=\"10\" fill=\"{ALLOCATED_COLOR}\"/>\
+         <text x=\"{}\" y=\"15\">Allocated</text>\
+         <rect x=\"{}\" y=\"6\" width=\"10\" height=\"10\" fill=\"{SPENT_COLOR}\"/>\
+         <text x=\"{}\" y=\"15\">Spent</text>",
+        MARGIN_LEFT + 14.0,
+        MARGIN_LEFT + 80.0,
+        MARGIN_LEFT + 94.0,
+    );
+
+    // Y axis with gridlines and amount labels
+    let mut tick = 0.0;
+    while tick <= scale_max + f64::EPSILON {
+        let tick_y = y(tick);
+        let _ = write!(
+            svg,
+            "<line x1=\"{MARGIN_LEFT}\" y1=\"{tick_y:.1}\" x2=\"{:.1}\" y2=\"{tick_y:.1}\" stroke=\"#e9ecef\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            MARGIN_LEFT + plot_width,
+            MARGIN_LEFT - 6.0,
+            tick_y + 4.0,
+            format_base_whole(tick, base),
+        );
+        tick += step;
+    }
+    let _ = write!(
+        svg,
+        "<text transform=\"translate(14 {:.1}) rotate(-90)\" text-anchor=\"middle\">Amount ({})</text>\
+         <line x1=\"{MARGIN_LEFT}\" y1=\"{MARGIN_TOP}\" x2=\"{MARGIN_LEFT}\" y2=\"{axis_y}\" stroke=\"#6c757d\"/>\
+         <line x1=\"{MARGIN_LEFT}\" y1=\"{axis_y}\" x2=\"{:.1}\" y2=\"{axis_y}\" stroke=\"#6c757d\"/>",
+        MARGIN_TOP + PLOT_HEIGHT / 2.0,
+        base,
+        MARGIN_LEFT + plot_width,
+    );
+
+    for (index, category) in categories.iter().enumerate() {
+        let name = escape_html(&category.name);
+        let group_x =
+            MARGIN_LEFT + index as f64 * GROUP_WIDTH + (GROUP_WIDTH - 2.0 * BAR_WIDTH) / 2.0;
+        let spent_color = if category.spent_amount > category.allocated_amount {
+            OVERSPENT_COLOR
+        } else {
+            SPENT_COLOR
+        };
+
+        for (offset, amount, color, label) in [
+            (0.0, category.allocated_amount, ALLOCATED_COLOR, "allocated"),
+            (BAR_WIDTH, category.spent_amount, spent_color, "spent"),
+        ] {
+            let top = y(from_minor_units(amount, base));
+            // Keep zero amounts visible as a thin line on the axis
+            let bar_height = (axis_y - top).max(1.0);
+            let _ = write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{BAR_WIDTH}\" height=\"{bar_height:.1}\" fill=\"{color}\">\
+                 <title>{name}: {} {label}</title></rect>",
+                group_x + offset,
+                axis_y - bar_height,
+                format_base(amount, base),
+            );
+        }
+
+        let label_x = group_x + BAR_WIDTH;
+        let label_y = axis_y + 12.0;
+        let _ = write!(
+            svg,
+            "<text transform=\"translate({label_x:.1} {label_y:.1}) rotate(-35)\" text-anchor=\"end\">\
+             <title>{name}</title>{}</text>",
+            escape_html(&short_label(&category.name)),
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+// Layout of the net worth chart, in SVG user units.
+const LINE_PLOT_WIDTH: f64 = 640.0;
+// Room right of the plot for the last date label
+const LINE_MARGIN_RIGHT: f64 = 28.0;
+const LINE_MARGIN_BOTTOM: f64 = 32.0;
+const LINE_COLOR: &str = "#28a745";
+// Dates labelled along the bottom
+const X_LABELS: usize = 6;
+// Past this many snapshots points are left off and only the line is drawn
+const MAX_POINTS: usize = 90;
+
+// Total balance over time as an inline SVG line, spacing snapshots by date so
+// missed days show as longer segments. The scale fits the range of balances
+// rather than starting from zero, so changes stay visible. Amounts are in the
+// base currency `base`. Empty with fewer than two snapshots, as there is no
+// line to draw yet.
+pub fn networth_chart(snapshots: &[BalanceSnapshot], base: &str) -> String {
+    let days: Vec<(NaiveDate, f64)> = snapshots
+        .iter()
+        .filter_map(|snapshot| {
+            NaiveDate::parse_from_str(&snapshot.date, "%Y-%m-%d")
+                .ok()
+                .map(|date| (date, snapshot.total))
+        })
+        .collect();
+    if days.len() < 2 {
+        return String::new();
+    }
+    let first = days[0].0;
+    let last = days[days.len() - 1].0;
+
+    let low = days
+        .iter()
+        .map(|(_, total)| *total)
+        .fold(f64::MAX, f64::min);
+    let high = days
+        .iter()
+        .map(|(_, total)| *total)
+        .fold(f64::MIN, f64::max);
+    let step = tick_step((high - low).max(1.0));
+    let scale_min = (low / step).floor() * step;
+    let scale_max = ((high / step).ceil() * step).max(scale_min + step);
+    let span = (last - first).num_days().max(1) as f64;
+    let x =
+        |date: NaiveDate| MARGIN_LEFT + (date - first).num_days() as f64 / span * LINE_PLOT_WIDTH;
+    let y = |total: f64| {
+        MARGIN_TOP + PLOT_HEIGHT - (total - scale_min) / (scale_max - scale_min) * PLOT_HEIGHT
+    };
+
+    let width = MARGIN_LEFT + LINE_PLOT_WIDTH + LINE_MARGIN_RIGHT;
+    let height = MARGIN_TOP + PLOT_HEIGHT + LINE_MARGIN_BOTTOM;
+    let axis_y = MARGIN_TOP + PLOT_HEIGHT;
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<svg viewBox=\"0 0 {width:.0} {height:.0}\" width=\"100%\" style=\"max-width: {width:.0}px\" \
+         role=\"img\" aria-label=\"Total balance over time\" font-size=\"11\">"
+    );
+
+    // Y axis with gridlines and amount labels
+    let mut tick = scale_min;
+    while tick <= scale_max + f64::EPSILON {
+        let tick_y = y(tick);
+        let _ = write!(
+            svg,
+            "<line x1=\"{MARGIN_LEFT}\" y1=\"{tick_y:.1}\" x2=\"{:.1}\" y2=\"{tick_y:.1}\" stroke=\"#e9ecef\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            MARGIN_LEFT + LINE_PLOT_WIDTH,
+            MARGIN_LEFT - 6.0,
+            tick_y + 4.0,
+            format_base_whole(tick, base),
+        );
+        tick += step;
+    }
+    let _ = write!(
+        svg,
+        "<text transform=\"translate(14 {:.1}) rotate(-90)\" text-anchor=\"middle\">Balance ({})</text>\
+         <line x1=\"{MARGIN_LEFT}\" y1=\"{MARGIN_TOP}\" x2=\"{MARGIN_LEFT}\" y2=\"{axis_y}\" stroke=\"#6c757d\"/>\
+         <line x1=\"{MARGIN_LEFT}\" y1=\"{axis_y}\" x2=\"{:.1}\" y2=\"{axis_y}\" stroke=\"#6c757d\"/>",
+        MARGIN_TOP + PLOT_HEIGHT / 2.0,
+        base,
+        MARGIN_LEFT + LINE_PLOT_WIDTH,
+    );
+
+    // Evenly spaced dates along the bottom
+    let span_days = (last - first).num_days();
+    let labels = (X_LABELS as i64 - 1).min(span_days).max(1);
+    for index in 0..=labels {
+        let date = first + chrono::Duration::days(span_days * index / labels);
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x(date),
+            axis_y + 16.0,
+            date.format("%-d %b"),
+        );
+    }
+
+    let points: Vec<String> = days
+        .iter()
+        .map(|(date, total)| format!("{:.1},{:.1}", x(*date), y(*total)))
+        .collect();
+    let _ = write!(
+        svg,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"{LINE_COLOR}\" stroke-width=\"2\"/>",
+        points.join(" ")
+    );
+    if days.len() <= MAX_POINTS {
+        for (date, total) in &days {
+            let _ = write!(
+                svg,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{LINE_COLOR}\">\
+                 <title>{}: {}</title></circle>",
+                x(*date),
+                y(*total),
+                date.format("%-d %b %Y"),
+                format_base(*total, base),
+            );
+        }
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
@@ -0,0 +1,962 @@
+use chrono_tz::Tz;
+use log::warn;
+use regex::RegexBuilder;
+use serde::Deserialize;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::alerts::{load_alert_config, AlertConfig};
+use crate::money::to_minor_units;
+use crate::up::{RetryPolicy, Timeouts};
+use crate::{
+    get_budget_categories, get_category_rules, normalize_words, Allocation, BudgetCategory,
+    CategoryRule, SplitRule, Transaction,
+};
+
+#[derive(Deserialize)]
+struct CategoriesFile {
+    #[serde(default)]
+    rollover: Rollover,
+    categories: Vec<CategoryConfig>,
+    // Catches transactions no rule matches; "Other" with no allocation when
+    // omitted
+    fallback: Option<CategoryConfig>,
+    // Month-specific dollar amounts keyed by "YYYY-MM" then category name,
+    // e.g. `allocations.2024-07.Utilities = 450.0`
+    #[serde(default)]
+    allocations: BTreeMap<String, HashMap<String, f64>>,
+    // Alternative category sets by name, picked with `/budget?template=`
+    #[serde(default)]
+    templates: BTreeMap<String, TemplateConfig>,
+    // The template used unless another is asked for; the top-level
+    // categories, "default", when omitted
+    default_template: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TemplateConfig {
+    categories: Vec<CategoryConfig>,
+    fallback: Option<CategoryConfig>,
+}
+
+// The name of the budget made of the top-level `[[categories]]`.
+pub const DEFAULT_TEMPLATE: &str = "default";
+
+// Envelope budgeting: what carries over from last month's remaining amount
+// into this month's allocation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rollover {
+    // Every month starts fresh
+    #[default]
+    Off,
+    // Unspent money carries forward; overspending is forgiven
+    Unspent,
+    // Both unspent money and overspending carry forward
+    All,
+}
+
+impl Rollover {
+    // The part of a category's remaining amount carried into the next month.
+    pub fn carried(self, remaining: i64) -> i64 {
+        match self {
+            Rollover::Off => 0,
+            Rollover::Unspent => remaining.max(0),
+            Rollover::All => remaining,
+        }
+    }
+}
+
+// Allocations in minor units by category name for each overridden
+// `(year, month)`.
+type MonthlyOverrides = HashMap<(i32, u32), HashMap<String, i64>>;
+
+// The configured budget categories, by template name, plus any per-month
+// allocation overrides.
+pub struct BudgetConfig {
+    templates: BTreeMap<String, Vec<BudgetCategory>>,
+    default_template: String,
+    overrides: MonthlyOverrides,
+    rollover: Rollover,
+}
+
+impl BudgetConfig {
+    // The default template's categories as configured, without any monthly
+    // overrides applied.
+    pub fn categories(&self) -> &[BudgetCategory] {
+        &self.templates[&self.default_template]
+    }
+
+    pub fn rollover(&self) -> Rollover {
+        self.rollover
+    }
+
+    pub fn default_template(&self) -> &str {
+        &self.default_template
+    }
+
+    pub fn template_names(&self) -> Vec<String> {
+        self.templates.keys().cloned().collect()
+    }
+
+    // The default template's categories for a given month, with that month's
+    // overrides replacing the base allocations. Categories without an
+    // override keep theirs.
+    pub fn resolve_allocations(&self, year: i32, month: u32) -> Vec<BudgetCategory> {
+        self.resolve(self.categories(), year, month)
+    }
+
+    // `resolve_allocations` for the named template, `None` if there is no
+    // such template.
+    pub fn resolve_template(
+        &self,
+        name: &str,
+        year: i32,
+        month: u32,
+    ) -> Option<Vec<BudgetCategory>> {
+        self.templates
+            .get(name)
+            .map(|categories| self.resolve(categories, year, month))
+    }
+
+    fn resolve(&self, categories: &[BudgetCategory], year: i32, month: u32) -> Vec<BudgetCategory> {
+        let mut categories = categories.to_vec();
+        if let Some(overrides) = self.overrides.get(&(year, month)) {
+            for category in &mut categories {
+                if let Some(&amount) = overrides.get(&category.name) {
+                    category.allocation = Allocation::Fixed(amount);
+                    category.allocated_amount = amount;
+                }
+            }
+        }
+        categories
+    }
+}
+
+// A category as written in the config file, with exactly one of a dollar
+// amount or a percentage of income, and optionally how its card looks.
+#[derive(Deserialize)]
+struct CategoryConfig {
+    name: String,
+    allocated_amount: Option<f64>,
+    allocated_percent: Option<f64>,
+    // CSS hex colour such as "#28a745"
+    color: Option<String>,
+    // Font Awesome icon name, with or without the "fa-" prefix
+    icon: Option<String>,
+}
+
+impl CategoryConfig {
+    // The category as configured, with a dollar amount held in minor units of
+    // the base currency `base`.
+    fn into_category(self, base: &str) -> Result<BudgetCategory, String> {
+        let allocation = match (self.allocated_amount, self.allocated_percent) {
+            (Some(amount), None) if amount.is_nan() || amount < 0.0 => {
+                return Err(format!(
+                    "category \"{}\" must have a non-negative allocated_amount, got {}",
+                    self.name, amount
+                ))
+            }
+            (Some(amount), None) => Allocation::Fixed(to_minor_units(amount, base)),
+            (None, Some(percent)) => Allocation::Percent(percent),
+            _ => {
+                return Err(format!(
+                    "category \"{}\" must set exactly one of allocated_amount or allocated_percent",
+                    self.name
+                ))
+            }
+        };
+        let mut category = BudgetCategory::new(&self.name, allocation);
+
+        if let Some(color) = self.color {
+            let color = color.trim();
+            let hex = color.strip_prefix('#').unwrap_or_default();
+            if !matches!(hex.len(), 3 | 6) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "category \"{}\" has color \"{}\"; use a hex colour like \"#28a745\"",
+                    self.name, color
+                ));
+            }
+            category.color = Some(color.to_string());
+        }
+
+        if let Some(icon) = self.icon {
+            let icon = icon.trim().to_lowercase();
+            let name = icon.strip_prefix("fa-").unwrap_or(&icon);
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(format!(
+                    "category \"{}\" has icon \"{}\"; use a Font Awesome name like \"shopping-cart\"",
+                    self.name, icon
+                ));
+            }
+            category.icon = Some(format!("fa-{}", name));
+        }
+
+        Ok(category)
+    }
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    rules: Vec<CategoryRule>,
+    #[serde(default)]
+    splits: Vec<SplitRule>,
+    // Up Bank category id to budget category name
+    #[serde(default)]
+    up_categories: HashMap<String, String>,
+    #[serde(default)]
+    ignore: IgnoreFilter,
+}
+
+// Everything in the rules file, read once at startup by `load_rules` and
+// shared with handlers as `web::Data<Rules>`.
+pub struct Rules {
+    pub categories: Vec<CategoryRule>,
+    pub splits: Vec<SplitRule>,
+    // The budget category for each Up Bank category id
+    pub up_categories: HashMap<String, String>,
+    pub ignore: IgnoreFilter,
+}
+
+#[derive(Deserialize)]
+struct RatesFile {
+    rates: HashMap<String, f64>,
+}
+
+// Static exchange rates into the base currency: one unit of each listed
+// currency is worth `rate` units of `base`.
+pub struct CurrencyRates {
+    base: String,
+    rates: HashMap<String, f64>,
+}
+
+impl CurrencyRates {
+    pub fn convert(&self, amount: f64, currency: &str) -> Option<f64> {
+        if currency.eq_ignore_ascii_case(&self.base) {
+            return Some(amount);
+        }
+        self.rates
+            .get(&currency.to_uppercase())
+            .map(|rate| amount * rate)
+    }
+
+    // Sets the transaction's amount in minor units of the base currency.
+    pub fn convert_transaction(&self, transaction: &mut Transaction) {
+        transaction.converted_amount = if transaction.currency.eq_ignore_ascii_case(&self.base) {
+            Some(transaction.amount)
+        } else {
+            self.convert(transaction.value(), &transaction.currency)
+                .map(|amount| to_minor_units(amount, &self.base))
+        };
+    }
+}
+
+// Transactions dropped from every view by description, such as round-up
+// transfers or cents of interest, from `[ignore]` in the rules file.
+// Descriptions are compared ignoring case.
+#[derive(Default, Deserialize)]
+pub struct IgnoreFilter {
+    // Descriptions matched in full
+    #[serde(default)]
+    exact: Vec<String>,
+    // Text matched anywhere in the description
+    #[serde(default)]
+    contains: Vec<String>,
+}
+
+impl IgnoreFilter {
+    pub fn ignores(&self, description: &str) -> bool {
+        let description = description.trim().to_lowercase();
+        self.exact.contains(&description)
+            || self
+                .contains
+                .iter()
+                .any(|text| description.contains(text.as_str()))
+    }
+}
+
+// Which accounts count towards budgets and expenses.
+#[derive(Clone)]
+pub enum AccountFilter {
+    All,
+    // Allowlist from `INCLUDE_ACCOUNTS`
+    Only(HashSet<String>),
+    // Blocklist from `EXCLUDE_ACCOUNTS`
+    Except(HashSet<String>),
+}
+
+impl AccountFilter {
+    pub fn allows(&self, account_id: &str) -> bool {
+        match self {
+            AccountFilter::All => true,
+            AccountFilter::Only(ids) => ids.contains(account_id),
+            AccountFilter::Except(ids) => !ids.contains(account_id),
+        }
+    }
+}
+
+// Where configuration values come from: the environment (and .env) first,
+// then the TOML file at `CONFIG_FILE` (config.toml by default), whose keys are
+// the variable names in lowercase. Problems are collected as they're found so
+// a bad setup is reported all at once instead of one restart at a time.
+pub struct Settings {
+    file: toml::Table,
+    errors: Vec<String>,
+}
+
+impl Settings {
+    fn load() -> Result<Self, String> {
+        let path = env::var("CONFIG_FILE").unwrap_or_else(|_| "config.toml".to_string());
+        let file = read_config_file(Path::new(path.trim()))
+            .map_err(|e| e.to_string())?
+            .unwrap_or_default();
+        Ok(Settings {
+            file,
+            errors: Vec::new(),
+        })
+    }
+
+    // The trimmed value of `name`, `None` when unset or blank. Arrays in the
+    // file are read as comma-separated lists.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match env::var(name) {
+            Ok(value) => value,
+            Err(_) => match self.file.get(&name.to_lowercase())? {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        toml::Value::String(value) => value.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+                other => other.to_string(),
+            },
+        };
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    }
+
+    // Parses `name` into `T`, or `default` when unset. `expected` describes a
+    // valid value for the error message.
+    pub fn parse<T: FromStr>(&mut self, name: &str, default: T, expected: &str) -> T {
+        self.parse_where(name, default, |_| true, expected)
+    }
+
+    // `parse` for values with limits beyond their type, such as ranges.
+    pub fn parse_where<T: FromStr>(
+        &mut self,
+        name: &str,
+        default: T,
+        valid: impl Fn(&T) -> bool,
+        expected: &str,
+    ) -> T {
+        let Some(value) = self.get(name) else {
+            return default;
+        };
+        match value.parse() {
+            Ok(parsed) if valid(&parsed) => parsed,
+            _ => {
+                self.error(format!("{} must be {}, got \"{}\"", name, expected, value));
+                default
+            }
+        }
+    }
+
+    pub fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+}
+
+// Everything the server is configured with, read and validated once at
+// startup and shared with handlers as `web::Data<Config>`.
+pub struct Config {
+    pub bind_address: String,
+    pub port: u16,
+    pub workers: usize,
+    // `None` disables keep-alive so every request gets a fresh connection
+    pub keep_alive: Option<Duration>,
+    pub shutdown_timeout_secs: u64,
+    // Wall-clock limit for a whole request; `None` leaves requests unbounded
+    pub request_timeout: Option<Duration>,
+    // Serve built-in sample data instead of calling Up Bank
+    pub demo_mode: bool,
+    pub api_key: String,
+    // The token guarding the dashboard. Authentication is off when unset.
+    pub app_token: Option<String>,
+    // The secret Up Bank returned when the webhook was registered. Webhooks
+    // are refused until one is configured.
+    pub webhook_secret: Option<String>,
+    pub database_path: PathBuf,
+    pub cache_ttl: Duration,
+    pub accounts_cache_ttl: Duration,
+    pub retry_policy: RetryPolicy,
+    pub timeouts: Timeouts,
+    pub max_pages: usize,
+    pub concurrency: usize,
+    pub display_tz: Tz,
+    pub fiscal_start_day: u32,
+    // Transactions at least this big, in minor units of the base currency,
+    // are highlighted; `None` highlights none
+    pub large_txn_threshold: Option<i64>,
+    pub base_currency: String,
+    pub account_filter: AccountFilter,
+    pub categories_path: PathBuf,
+    pub rates_path: PathBuf,
+    pub rules_path: PathBuf,
+    pub alerts: Option<AlertConfig>,
+    // How often to snapshot balances for `/networth`; `None` turns it off
+    pub snapshot_interval: Option<Duration>,
+}
+
+impl Config {
+    // Reads every setting, failing with all of the problems found, one per
+    // line, if any are invalid.
+    pub fn load() -> Result<Config, String> {
+        let mut settings = Settings::load()?;
+        let path = |settings: &Settings, name: &str, default: &str| {
+            PathBuf::from(settings.get(name).unwrap_or_else(|| default.to_string()))
+        };
+
+        // One worker per CPU by default. Each worker keeps its own memory, so
+        // small machines may want fewer.
+        let default_workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let workers = settings.parse_where(
+            "WORKERS",
+            default_workers,
+            |workers| *workers > 0,
+            &format!(
+                "a positive number of worker threads (default: {})",
+                default_workers
+            ),
+        );
+        // Holding idle connections open trades memory and sockets for faster
+        // repeat requests.
+        let keep_alive = settings.parse(
+            "KEEP_ALIVE_SECS",
+            5,
+            "a whole number of seconds, or 0 to disable it",
+        );
+        let request_timeout = settings.parse(
+            "REQUEST_TIMEOUT_SECS",
+            60,
+            "a whole number of seconds, or 0 for no limit",
+        );
+        let large_txn_threshold = settings.parse_where(
+            "LARGE_TXN_THRESHOLD",
+            0.0,
+            |threshold: &f64| *threshold >= 0.0,
+            "a positive amount, or 0 to highlight nothing",
+        );
+
+        let base_currency = settings
+            .get("BASE_CURRENCY")
+            .unwrap_or_else(|| "AUD".to_string())
+            .to_uppercase();
+        if base_currency.len() != 3 || !base_currency.chars().all(|c| c.is_ascii_alphabetic()) {
+            settings.error(format!(
+                "BASE_CURRENCY must be a three-letter currency code like \"AUD\", got \"{}\"",
+                base_currency
+            ));
+        }
+
+        // Demo mode never calls Up Bank, so needs no key. Its sample data is
+        // stored like the real thing, so by default it gets an in-memory
+        // database that is gone with the server
+        let demo_mode = settings.parse("DEMO_MODE", false, "true or false");
+        let api_key = if demo_mode {
+            String::new()
+        } else {
+            load_api_key(&settings).unwrap_or_else(|e| {
+                settings.error(e);
+                String::new()
+            })
+        };
+        let default_database = if demo_mode { ":memory:" } else { "budget.db" };
+        let account_filter = load_account_filter(&settings).unwrap_or_else(|e| {
+            settings.error(e);
+            AccountFilter::All
+        });
+        let timeouts = Timeouts {
+            request: Duration::from_secs(settings.parse(
+                "UP_TIMEOUT_SECS",
+                30,
+                "a whole number of seconds",
+            )),
+            connect: Duration::from_secs(settings.parse(
+                "UP_CONNECT_TIMEOUT_SECS",
+                10,
+                "a whole number of seconds",
+            )),
+        };
+        let alerts = load_alert_config(&mut settings, timeouts);
+        let snapshot_hours: u64 = settings.parse(
+            "SNAPSHOT_INTERVAL_HOURS",
+            6,
+            "a whole number of hours, or 0 to stop recording net worth",
+        );
+
+        let config = Config {
+            bind_address: settings
+                .get("BIND_ADDRESS")
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: settings.parse("PORT", 8080, "a number between 0 and 65535"),
+            workers,
+            keep_alive: (keep_alive > 0).then(|| Duration::from_secs(keep_alive)),
+            shutdown_timeout_secs: settings.parse(
+                "SHUTDOWN_TIMEOUT_SECS",
+                30,
+                "a whole number of seconds",
+            ),
+            request_timeout: (request_timeout > 0).then(|| Duration::from_secs(request_timeout)),
+            demo_mode,
+            api_key,
+            app_token: settings.get("APP_TOKEN"),
+            webhook_secret: settings.get("UP_WEBHOOK_SECRET"),
+            database_path: path(&settings, "DATABASE_PATH", default_database),
+            cache_ttl: Duration::from_secs(settings.parse(
+                "CACHE_TTL_SECONDS",
+                300,
+                "a whole number of seconds",
+            )),
+            accounts_cache_ttl: Duration::from_secs(settings.parse(
+                "ACCOUNTS_CACHE_TTL_SECONDS",
+                3600,
+                "a whole number of seconds",
+            )),
+            retry_policy: RetryPolicy {
+                max_retries: settings.parse("UP_MAX_RETRIES", 3, "a whole number of retries"),
+                base_delay: Duration::from_millis(settings.parse(
+                    "UP_RETRY_BASE_DELAY_MS",
+                    500,
+                    "a whole number of milliseconds",
+                )),
+            },
+            timeouts,
+            max_pages: settings.parse_where(
+                "UP_MAX_PAGES",
+                50,
+                |pages| *pages > 0,
+                "a positive number of pages",
+            ),
+            concurrency: settings.parse_where(
+                "UP_CONCURRENCY",
+                4,
+                |requests| *requests > 0,
+                "a positive number of requests",
+            ),
+            display_tz: settings.parse(
+                "DISPLAY_TZ",
+                Tz::Australia__Sydney,
+                "a timezone name like \"Australia/Sydney\"",
+            ),
+            fiscal_start_day: settings.parse_where(
+                "FISCAL_START_DAY",
+                1,
+                |day| (1..=31).contains(day),
+                "a day of the month between 1 and 31",
+            ),
+            large_txn_threshold: (large_txn_threshold > 0.0)
+                .then(|| to_minor_units(large_txn_threshold, &base_currency)),
+            base_currency,
+            account_filter,
+            categories_path: path(&settings, "CATEGORIES_FILE", "categories.toml"),
+            rates_path: path(&settings, "RATES_FILE", "rates.toml"),
+            rules_path: path(&settings, "RULES_FILE", "rules.toml"),
+            alerts,
+            snapshot_interval: (snapshot_hours > 0)
+                .then(|| Duration::from_secs(snapshot_hours * 60 * 60)),
+        };
+
+        if settings.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!(
+                "invalid configuration:\n  {}",
+                settings.errors.join("\n  ")
+            ))
+        }
+    }
+}
+
+// Hands the base currency to the `money` template filters, which can't see
+// the template's fields, see `render_template`.
+impl askama::Values for Config {
+    fn get_value<'a>(&'a self, key: &str) -> Option<&'a dyn Any> {
+        match key {
+            "base_currency" => Some(&self.base_currency),
+            _ => None,
+        }
+    }
+}
+
+// Comma-separated account ids from a setting, `None` when unset or empty.
+fn account_list(settings: &Settings, name: &str) -> Option<HashSet<String>> {
+    let ids: HashSet<String> = settings
+        .get(name)?
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect();
+    (!ids.is_empty()).then_some(ids)
+}
+
+// Reads `INCLUDE_ACCOUNTS` (only these accounts) or `EXCLUDE_ACCOUNTS` (every
+// account but these). Setting both is ambiguous, so it's an error.
+fn load_account_filter(settings: &Settings) -> Result<AccountFilter, String> {
+    match (
+        account_list(settings, "INCLUDE_ACCOUNTS"),
+        account_list(settings, "EXCLUDE_ACCOUNTS"),
+    ) {
+        (Some(_), Some(_)) => {
+            Err("set only one of INCLUDE_ACCOUNTS or EXCLUDE_ACCOUNTS".to_string())
+        }
+        (Some(ids), None) => Ok(AccountFilter::Only(ids)),
+        (None, Some(ids)) => Ok(AccountFilter::Except(ids)),
+        (None, None) => Ok(AccountFilter::All),
+    }
+}
+
+// The Up Bank token, read from the file at `API_KEY_FILE` when set (as with
+// Docker secrets), otherwise from `API_KEY`.
+fn load_api_key(settings: &Settings) -> Result<String, String> {
+    if let Some(path) = settings.get("API_KEY_FILE") {
+        let key = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read API_KEY_FILE {}: {}", path, e))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("API_KEY_FILE {} is empty", path));
+        }
+        return Ok(key.to_string());
+    }
+
+    settings.get("API_KEY").ok_or_else(|| "API_KEY is not set; add your Up Bank personal access token to the environment, .env or config.toml, or point API_KEY_FILE at a file containing it".to_string())
+}
+
+// Reads and parses a TOML config file, returning `None` when it does not exist.
+fn read_config_file<T>(path: &Path) -> Result<Option<T>, Box<dyn std::error::Error>>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            let parsed = toml::from_str(&contents)
+                .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+            Ok(Some(parsed))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("failed to read {}: {}", path.display(), e).into()),
+    }
+}
+
+// Reads the budget categories from a TOML file, falling back to the built-in
+// defaults when the file does not exist. Dollar amounts are in the base
+// currency `base`.
+pub fn load_budget_config(
+    path: &Path,
+    base: &str,
+) -> Result<BudgetConfig, Box<dyn std::error::Error>> {
+    let mut allocations = BTreeMap::new();
+    let mut rollover = Rollover::Off;
+    let mut templates = BTreeMap::new();
+    let mut default_template = DEFAULT_TEMPLATE.to_string();
+    match read_config_file::<CategoriesFile>(path)? {
+        Some(file) => {
+            templates.insert(
+                DEFAULT_TEMPLATE.to_string(),
+                template_categories(file.categories, file.fallback, base)?,
+            );
+            for (name, template) in file.templates {
+                let name = name.trim().to_string();
+                if name.is_empty() || templates.contains_key(&name) {
+                    return Err(format!("template name \"{}\" is empty or taken", name).into());
+                }
+                let categories = template_categories(template.categories, template.fallback, base)
+                    .map_err(|e| format!("template \"{}\": {}", name, e))?;
+                templates.insert(name, categories);
+            }
+            if let Some(name) = file.default_template {
+                let name = name.trim().to_string();
+                if !templates.contains_key(&name) {
+                    return Err(format!(
+                        "default_template \"{}\" is not one of the templates: {}",
+                        name,
+                        templates.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )
+                    .into());
+                }
+                default_template = name;
+            }
+            allocations = file.allocations;
+            rollover = file.rollover;
+        }
+        None => {
+            warn!(
+                "{} not found, using default budget categories",
+                path.display()
+            );
+            templates.insert(DEFAULT_TEMPLATE.to_string(), get_budget_categories(base));
+        }
+    };
+
+    let all_categories: Vec<BudgetCategory> = templates.values().flatten().cloned().collect();
+    let overrides = parse_overrides(allocations, &all_categories, base)?;
+    Ok(BudgetConfig {
+        templates,
+        default_template,
+        overrides,
+        rollover,
+    })
+}
+
+// One template's categories, with its fallback last, checked for mistakes.
+fn template_categories(
+    categories: Vec<CategoryConfig>,
+    fallback: Option<CategoryConfig>,
+    base: &str,
+) -> Result<Vec<BudgetCategory>, String> {
+    let mut categories: Vec<BudgetCategory> = categories
+        .into_iter()
+        .map(|category| category.into_category(base))
+        .collect::<Result<_, _>>()?;
+    if let Some(fallback) = fallback {
+        let mut fallback = fallback.into_category(base)?;
+        fallback.is_fallback = true;
+        categories.push(fallback);
+    }
+    validate_categories(&categories)?;
+    Ok(categories)
+}
+
+// Checks the monthly overrides refer to real months and categories, keying
+// them by `(year, month)` for lookup, with amounts in minor units of `base`.
+fn parse_overrides(
+    allocations: BTreeMap<String, HashMap<String, f64>>,
+    categories: &[BudgetCategory],
+    base: &str,
+) -> Result<MonthlyOverrides, String> {
+    let mut overrides = HashMap::new();
+    for (key, amounts) in allocations {
+        let month = key
+            .split_once('-')
+            .and_then(|(year, month)| Some((year.parse().ok()?, month.parse().ok()?)))
+            .filter(|&(_, month): &(i32, u32)| (1..=12).contains(&month))
+            .ok_or_else(|| format!("allocation month \"{}\" must look like 2024-07", key))?;
+        for (name, &amount) in &amounts {
+            if !categories.iter().any(|category| &category.name == name) {
+                return Err(format!(
+                    "allocations for {} refer to unknown category \"{}\"",
+                    key, name
+                ));
+            }
+            if amount.is_nan() || amount < 0.0 {
+                return Err(format!(
+                    "allocation for \"{}\" in {} must be non-negative, got {}",
+                    name, key, amount
+                ));
+            }
+        }
+        let amounts = amounts
+            .into_iter()
+            .map(|(name, amount)| (name, to_minor_units(amount, base)))
+            .collect();
+        overrides.insert(month, amounts);
+    }
+    Ok(overrides)
+}
+
+// Reads the rules file once: the categorizer's keyword rules, splits, Up Bank
+// category mapping and ignored descriptions. Without the file the default
+// keyword rules apply and the rest is empty.
+pub fn load_rules(path: &Path) -> Result<Rules, Box<dyn std::error::Error>> {
+    let Some(file) = read_config_file::<RulesFile>(path)? else {
+        warn!(
+            "{} not found, using default categorization rules",
+            path.display()
+        );
+        return Ok(Rules {
+            categories: category_rules(get_category_rules())?,
+            splits: Vec::new(),
+            up_categories: HashMap::new(),
+            ignore: IgnoreFilter::default(),
+        });
+    };
+
+    Ok(Rules {
+        categories: category_rules(file.rules)?,
+        splits: split_rules(file.splits)?,
+        up_categories: up_category_map(file.up_categories)?,
+        ignore: ignore_filter(file.ignore)?,
+    })
+}
+
+// Prepares the keyword rules used by the categorizer. Rules are kept in file
+// order, which is also the order they are tried in. Keywords are normalized
+// and regexes compiled once here so matching doesn't have to redo it per
+// transaction, and a bad pattern stops startup rather than never matching.
+fn category_rules(
+    mut rules: Vec<CategoryRule>,
+) -> Result<Vec<CategoryRule>, Box<dyn std::error::Error>> {
+    for rule in &mut rules {
+        if rule.regex {
+            for keyword in &rule.keywords {
+                if keyword.is_empty() {
+                    return Err(format!("rule for \"{}\" has an empty regex", rule.category).into());
+                }
+                let pattern = RegexBuilder::new(keyword)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        format!(
+                            "rule for \"{}\" has an invalid regex \"{}\": {}",
+                            rule.category, keyword, e
+                        )
+                    })?;
+                rule.patterns.push(pattern);
+            }
+            continue;
+        }
+        for keyword in &mut rule.keywords {
+            *keyword = normalize_words(keyword);
+            if keyword.is_empty() {
+                return Err(format!("rule for \"{}\" has an empty keyword", rule.category).into());
+            }
+        }
+    }
+
+    Ok(rules)
+}
+
+// Checks the `[[splits]]` from the rules file. Each must name either a
+// transaction id or a description, and its shares must add up to 100%.
+fn split_rules(mut splits: Vec<SplitRule>) -> Result<Vec<SplitRule>, Box<dyn std::error::Error>> {
+    for split in &mut splits {
+        let name = match (&split.transaction_id, &mut split.description) {
+            (Some(id), None) => format!("transaction {}", id),
+            (None, Some(description)) => {
+                let name = format!("\"{}\"", description);
+                *description = normalize_words(description);
+                if description.is_empty() {
+                    return Err("split has an empty description".into());
+                }
+                name
+            }
+            _ => {
+                return Err("each split needs exactly one of transaction_id or description".into())
+            }
+        };
+        if split.shares.is_empty() {
+            return Err(format!("split for {} has no shares", name).into());
+        }
+        if let Some((category, percent)) = split
+            .shares
+            .iter()
+            .find(|(_, percent)| percent.is_nan() || **percent <= 0.0)
+        {
+            return Err(format!(
+                "split for {} gives {} a share of {}%; shares must be positive",
+                name, category, percent
+            )
+            .into());
+        }
+        let total: f64 = split.shares.values().sum();
+        if (total - 100.0).abs() > 0.01 {
+            return Err(format!("split for {} adds up to {}%, not 100%", name, total).into());
+        }
+    }
+
+    Ok(splits)
+}
+
+// Checks `[up_categories]` from the rules file, mapping Up Bank category ids
+// such as "groceries" to budget category names.
+fn up_category_map(
+    up_categories: HashMap<String, String>,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut map = HashMap::new();
+    for (id, category) in up_categories {
+        let category = category.trim();
+        if category.is_empty() {
+            return Err(format!("Up Bank category \"{}\" maps to an empty name", id).into());
+        }
+        map.insert(id.trim().to_lowercase(), category.to_string());
+    }
+    Ok(map)
+}
+
+// Normalizes the `[ignore]` patterns from the rules file, ready for matching.
+fn ignore_filter(ignore: IgnoreFilter) -> Result<IgnoreFilter, Box<dyn std::error::Error>> {
+    let normalize = |patterns: Vec<String>, kind: &str| {
+        patterns
+            .into_iter()
+            .map(|pattern| {
+                let pattern = pattern.trim().to_lowercase();
+                if pattern.is_empty() {
+                    Err(format!("ignore.{} has an empty pattern", kind))
+                } else {
+                    Ok(pattern)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(IgnoreFilter {
+        exact: normalize(ignore.exact, "exact")?,
+        contains: normalize(ignore.contains, "contains")?,
+    })
+}
+
+// Reads the exchange rate table, which is optional when everything is already
+// in the base currency.
+pub fn load_currency_rates(
+    path: &Path,
+    base: &str,
+) -> Result<CurrencyRates, Box<dyn std::error::Error>> {
+    let rates = read_config_file::<RatesFile>(path)?
+        .map(|file| file.rates)
+        .unwrap_or_default();
+
+    let mut normalized = HashMap::new();
+    for (currency, rate) in rates {
+        if rate.is_nan() || rate <= 0.0 {
+            return Err(format!("rate for {} must be positive, got {}", currency, rate).into());
+        }
+        normalized.insert(currency.trim().to_uppercase(), rate);
+    }
+
+    Ok(CurrencyRates {
+        base: base.trim().to_uppercase(),
+        rates: normalized,
+    })
+}
+
+fn validate_categories(categories: &[BudgetCategory]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+
+    for category in categories {
+        match category.allocation {
+            Allocation::Percent(percent) if !(0.0..=100.0).contains(&percent) => {
+                return Err(format!(
+                    "category \"{}\" must have an allocated_percent between 0 and 100, got {}",
+                    category.name, percent
+                ));
+            }
+            _ => {}
+        }
+        if !seen.insert(category.name.as_str()) {
+            return Err(format!(
+                "category \"{}\" is defined more than once",
+                category.name
+            ));
+        }
+    }
+
+    Ok(())
+}
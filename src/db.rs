@@ -0,0 +1,436 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use crate::networth::BalanceSnapshot;
+use crate::{month_range, BudgetCategory, Transaction};
+
+// Schema migrations, applied in order. The index of the last applied
+// migration is tracked in SQLite's `user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS transactions (
+        id TEXT PRIMARY KEY,
+        account_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        created_at_utc TEXT NOT NULL,
+        description TEXT NOT NULL,
+        amount REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS transactions_created_at_utc
+        ON transactions (created_at_utc);
+    CREATE TABLE IF NOT EXISTS budget_categories (
+        year INTEGER NOT NULL,
+        month INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        allocated_amount REAL NOT NULL,
+        spent_amount REAL NOT NULL,
+        income_amount REAL NOT NULL,
+        PRIMARY KEY (year, month, name)
+    );
+    CREATE TABLE IF NOT EXISTS synced_months (
+        year INTEGER NOT NULL,
+        month INTEGER NOT NULL,
+        synced_at TEXT NOT NULL,
+        PRIMARY KEY (year, month)
+    );",
+    // Stored months predate tags, so have them fetched again
+    "ALTER TABLE transactions ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+     DELETE FROM synced_months;",
+    // Likewise for whether a transaction is still held
+    "ALTER TABLE transactions ADD COLUMN status TEXT NOT NULL DEFAULT 'SETTLED';
+     DELETE FROM synced_months;",
+    // Likewise for the currency of foreign transactions
+    "ALTER TABLE transactions ADD COLUMN currency TEXT NOT NULL DEFAULT 'AUD';
+     DELETE FROM synced_months;",
+    // Likewise for round-ups
+    "ALTER TABLE transactions ADD COLUMN round_up REAL;
+     DELETE FROM synced_months;",
+    // Likewise for settlement times
+    "ALTER TABLE transactions ADD COLUMN settled_at TEXT;
+     DELETE FROM synced_months;",
+    // Likewise for internal transfers
+    "ALTER TABLE transactions ADD COLUMN is_transfer INTEGER NOT NULL DEFAULT 0;
+     DELETE FROM synced_months;",
+    "CREATE TABLE IF NOT EXISTS alerts_sent (
+        year INTEGER NOT NULL,
+        month INTEGER NOT NULL,
+        category TEXT NOT NULL,
+        threshold REAL NOT NULL,
+        sent_at TEXT NOT NULL,
+        PRIMARY KEY (year, month, category, threshold)
+    );",
+    // Stored months predate Up Bank categories, so have them fetched again
+    "ALTER TABLE transactions ADD COLUMN up_category TEXT;
+     DELETE FROM synced_months;",
+    // Likewise for receipts attached in the Up app
+    "ALTER TABLE transactions ADD COLUMN attachment_id TEXT;
+     DELETE FROM synced_months;",
+    "CREATE TABLE IF NOT EXISTS balance_snapshots (
+        date TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        total REAL NOT NULL,
+        taken_at TEXT NOT NULL,
+        PRIMARY KEY (date, currency)
+    );",
+    // Amounts move from REAL dollars to INTEGER minor units, which SQLite
+    // can only do by rebuilding the table. Yen and the like have no minor
+    // unit, so their amounts are already whole.
+    "BEGIN;
+     CREATE TABLE transactions_minor_units (
+        id TEXT PRIMARY KEY,
+        account_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        created_at_utc TEXT NOT NULL,
+        description TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        tags TEXT NOT NULL DEFAULT '[]',
+        status TEXT NOT NULL DEFAULT 'SETTLED',
+        currency TEXT NOT NULL DEFAULT 'AUD',
+        round_up INTEGER,
+        settled_at TEXT,
+        is_transfer INTEGER NOT NULL DEFAULT 0,
+        up_category TEXT,
+        attachment_id TEXT
+     );
+     INSERT INTO transactions_minor_units
+        SELECT id, account_id, created_at, created_at_utc, description,
+            CAST(ROUND(amount * scale) AS INTEGER), tags, status, currency,
+            CAST(ROUND(round_up * scale) AS INTEGER), settled_at, is_transfer,
+            up_category, attachment_id
+        FROM (
+            SELECT *, CASE WHEN upper(currency) IN ('JPY', 'KRW', 'IDR', 'VND')
+                THEN 1 ELSE 100 END AS scale
+            FROM transactions
+        );
+     DROP TABLE transactions;
+     ALTER TABLE transactions_minor_units RENAME TO transactions;
+     CREATE INDEX transactions_created_at_utc ON transactions (created_at_utc);
+     COMMIT;",
+    // A month's dates depend on FISCAL_START_DAY, so records keep the start
+    // of the period they covered. Syncs from before this are of unknown range
+    // and get fetched again
+    "ALTER TABLE synced_months ADD COLUMN period_start TEXT;
+     ALTER TABLE budget_categories ADD COLUMN period_start TEXT;",
+    // Budget totals move to INTEGER minor units of the base currency too.
+    // Snapshots of a zero-decimal base are off until their month is viewed
+    // again, which records it afresh
+    "BEGIN;
+     CREATE TABLE budget_categories_minor_units (
+        year INTEGER NOT NULL,
+        month INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        allocated_amount INTEGER NOT NULL,
+        spent_amount INTEGER NOT NULL,
+        income_amount INTEGER NOT NULL,
+        period_start TEXT,
+        PRIMARY KEY (year, month, name)
+     );
+     INSERT INTO budget_categories_minor_units
+        SELECT year, month, name, CAST(ROUND(allocated_amount * 100) AS INTEGER),
+            CAST(ROUND(spent_amount * 100) AS INTEGER),
+            CAST(ROUND(income_amount * 100) AS INTEGER), period_start
+        FROM budget_categories;
+     DROP TABLE budget_categories;
+     ALTER TABLE budget_categories_minor_units RENAME TO budget_categories;
+     COMMIT;",
+];
+
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(migration)?;
+        conn.pragma_update(None, "user_version", index + 1)?;
+    }
+    Ok(())
+}
+
+// SQLite-backed store for fetched transactions and monthly budget snapshots.
+pub struct Database {
+    conn: Mutex<Connection>,
+    // Day of the month budget periods start on, which decides the dates each
+    // month's records cover
+    start_day: u32,
+}
+
+// Normalizes an Up Bank timestamp (which carries a local offset) to UTC so
+// rows can be range-queried with plain string comparison.
+fn utc_timestamp(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|date| {
+            date.with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+impl Database {
+    pub fn open(path: &Path, start_day: u32) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        migrate(&conn)?;
+
+        Ok(Database {
+            conn: Mutex::new(conn),
+            start_day,
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Inserts or updates transactions by their Up Bank id, so re-saving a
+    // month that was already synced is idempotent.
+    pub fn save_transactions(&self, transactions: &[Transaction]) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO transactions
+                    (id, account_id, created_at, created_at_utc, description, amount, tags, status, currency, round_up, settled_at, is_transfer, up_category, attachment_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT (id) DO UPDATE SET
+                    account_id = excluded.account_id,
+                    created_at = excluded.created_at,
+                    created_at_utc = excluded.created_at_utc,
+                    description = excluded.description,
+                    amount = excluded.amount,
+                    tags = excluded.tags,
+                    status = excluded.status,
+                    currency = excluded.currency,
+                    round_up = excluded.round_up,
+                    settled_at = excluded.settled_at,
+                    is_transfer = excluded.is_transfer,
+                    up_category = excluded.up_category,
+                    attachment_id = excluded.attachment_id",
+            )?;
+            for transaction in transactions {
+                statement.execute(params![
+                    transaction.id,
+                    transaction.account_id,
+                    transaction.date,
+                    utc_timestamp(&transaction.date),
+                    transaction.description,
+                    transaction.amount,
+                    serde_json::to_string(&transaction.tags).unwrap_or_default(),
+                    transaction.status,
+                    transaction.currency,
+                    transaction.round_up,
+                    transaction.settled_at,
+                    transaction.is_transfer,
+                    transaction.up_category,
+                    transaction.attachment_id,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn delete_transaction(&self, id: &str) -> rusqlite::Result<()> {
+        self.conn()
+            .execute("DELETE FROM transactions WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn load_transactions(&self, year: i32, month: u32) -> rusqlite::Result<Vec<Transaction>> {
+        let (start_date, end_date) = month_range(self.start_day, year, month);
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT id, account_id, created_at, description, amount, tags, status, currency, round_up, settled_at, is_transfer, up_category, attachment_id
+             FROM transactions
+             WHERE created_at_utc >= ?1 AND created_at_utc < ?2
+             ORDER BY created_at_utc DESC",
+        )?;
+        let rows = statement.query_map(params![start_date, end_date], |row| {
+            Ok(Transaction {
+                id: row.get(0)?,
+                account_id: row.get(1)?,
+                date: row.get(2)?,
+                description: row.get(3)?,
+                amount: row.get(4)?,
+                tags: serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or_default(),
+                status: row.get(6)?,
+                currency: row.get(7)?,
+                round_up: row.get(8)?,
+                converted_amount: None,
+                settled_at: row.get(9)?,
+                is_transfer: row.get(10)?,
+                up_category: row.get(11)?,
+                attachment_id: row.get(12)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    // Records the categorized totals for a month.
+    pub fn save_budget(
+        &self,
+        year: i32,
+        month: u32,
+        categories: &[BudgetCategory],
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO budget_categories
+                    (year, month, name, allocated_amount, spent_amount, income_amount, period_start)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT (year, month, name) DO UPDATE SET
+                    allocated_amount = excluded.allocated_amount,
+                    spent_amount = excluded.spent_amount,
+                    income_amount = excluded.income_amount,
+                    period_start = excluded.period_start",
+            )?;
+            let (period_start, _) = month_range(self.start_day, year, month);
+            // A period now has other categories than those last recorded
+            tx.execute(
+                "DELETE FROM budget_categories
+                 WHERE year = ?1 AND month = ?2 AND period_start IS NOT ?3",
+                params![year, month, period_start],
+            )?;
+            for category in categories {
+                statement.execute(params![
+                    year,
+                    month,
+                    category.name,
+                    category.allocated_amount,
+                    category.spent_amount,
+                    category.income_amount,
+                    period_start,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    // Each category's remaining amount (allocated minus spent) in a month's
+    // snapshot, empty when the month was never recorded or was recorded for
+    // different dates. Snapshots from before periods were kept are trusted.
+    pub fn load_remaining(&self, year: i32, month: u32) -> rusqlite::Result<Vec<(String, i64)>> {
+        let (period_start, _) = month_range(self.start_day, year, month);
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT name, allocated_amount - spent_amount
+             FROM budget_categories
+             WHERE year = ?1 AND month = ?2
+                AND (period_start IS NULL OR period_start = ?3)",
+        )?;
+        let rows = statement.query_map(params![year, month, period_start], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    // Whether a budget alert for this category and threshold already went out
+    // this month.
+    pub fn alert_sent(
+        &self,
+        year: i32,
+        month: u32,
+        category: &str,
+        threshold: f64,
+    ) -> rusqlite::Result<bool> {
+        self.conn().query_row(
+            "SELECT EXISTS (
+                SELECT 1 FROM alerts_sent
+                WHERE year = ?1 AND month = ?2 AND category = ?3 AND threshold = ?4
+             )",
+            params![year, month, category, threshold],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn mark_alert_sent(
+        &self,
+        year: i32,
+        month: u32,
+        category: &str,
+        threshold: f64,
+    ) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO alerts_sent (year, month, category, threshold, sent_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                year,
+                month,
+                category,
+                threshold,
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Records a day's total balance, replacing an earlier snapshot that day.
+    pub fn save_snapshot(&self, snapshot: &BalanceSnapshot) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT INTO balance_snapshots (date, currency, total, taken_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (date, currency) DO UPDATE SET
+                total = excluded.total,
+                taken_at = excluded.taken_at",
+            params![
+                snapshot.date,
+                snapshot.currency,
+                snapshot.total,
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Every snapshot taken in `currency`, oldest first.
+    pub fn load_snapshots(&self, currency: &str) -> rusqlite::Result<Vec<BalanceSnapshot>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT date, total, currency
+             FROM balance_snapshots
+             WHERE currency = ?1
+             ORDER BY date",
+        )?;
+        let rows = statement.query_map(params![currency], |row| {
+            Ok(BalanceSnapshot {
+                date: row.get(0)?,
+                total: row.get(1)?,
+                currency: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn mark_synced(&self, year: i32, month: u32) -> rusqlite::Result<()> {
+        let (period_start, _) = month_range(self.start_day, year, month);
+        self.conn().execute(
+            "INSERT INTO synced_months (year, month, synced_at, period_start)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (year, month) DO UPDATE SET
+                synced_at = excluded.synced_at,
+                period_start = excluded.period_start",
+            params![
+                year,
+                month,
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                period_start
+            ],
+        )?;
+        Ok(())
+    }
+
+    // A month is complete once it was synced after it ended; nothing new can
+    // show up for it, so it can be served from the database alone. The sync
+    // must have covered the same dates the month has now.
+    pub fn is_month_complete(&self, year: i32, month: u32) -> rusqlite::Result<bool> {
+        let (month_start, month_end) = month_range(self.start_day, year, month);
+        let complete = self.conn().query_row(
+            "SELECT EXISTS (
+                SELECT 1 FROM synced_months
+                WHERE year = ?1 AND month = ?2 AND synced_at >= ?3 AND period_start = ?4
+             )",
+            params![year, month, month_end, month_start],
+            |row| row.get(0),
+        )?;
+        Ok(complete)
+    }
+}
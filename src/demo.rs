@@ -0,0 +1,119 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::up::{parse_transaction, Account, CategoryResource, UpCategory};
+use crate::Transaction;
+
+// Sample accounts, categories and transactions served instead of Up Bank's in
+// `DEMO_MODE`, written in Up Bank's own API format so they go through the
+// same parsing as the real thing.
+const FIXTURE: &str = include_str!("../fixtures/demo.json");
+
+#[derive(Deserialize)]
+struct Fixture {
+    accounts: Vec<Value>,
+    categories: Vec<Value>,
+    transactions: Vec<Value>,
+}
+
+// The demo data, with the transactions moved forward so the newest was made
+// yesterday and the current month always has something in it.
+pub struct DemoData {
+    accounts: Vec<Value>,
+    categories: Vec<UpCategory>,
+    transactions: Vec<Transaction>,
+}
+
+// Moves an RFC 3339 timestamp `days` later, keeping its offset.
+fn shift_timestamp(timestamp: &str, days: i64) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|date| (date + Duration::days(days)).to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn parse_utc(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+impl DemoData {
+    pub fn load() -> DemoData {
+        let fixture: Fixture =
+            serde_json::from_str(FIXTURE).expect("the demo fixture is valid JSON");
+        let mut transactions: Vec<Transaction> =
+            fixture.transactions.iter().map(parse_transaction).collect();
+
+        let newest = transactions
+            .iter()
+            .filter_map(|transaction| parse_utc(&transaction.date))
+            .max();
+        if let Some(newest) = newest {
+            let days = (Utc::now() - newest).num_days() - 1;
+            for transaction in &mut transactions {
+                transaction.date = shift_timestamp(&transaction.date, days);
+                transaction.settled_at = transaction
+                    .settled_at
+                    .as_deref()
+                    .map(|settled_at| shift_timestamp(settled_at, days));
+            }
+        }
+
+        DemoData {
+            accounts: fixture.accounts,
+            categories: fixture
+                .categories
+                .into_iter()
+                .map(|category| {
+                    serde_json::from_value::<CategoryResource>(category)
+                        .expect("demo categories are valid")
+                        .into()
+                })
+                .collect(),
+            transactions,
+        }
+    }
+
+    pub fn accounts(&self) -> Vec<Account> {
+        self.accounts
+            .iter()
+            .map(|account| {
+                serde_json::from_value(account.clone()).expect("demo accounts are valid")
+            })
+            .collect()
+    }
+
+    pub fn categories(&self) -> &[UpCategory] {
+        &self.categories
+    }
+
+    pub fn transaction(&self, id: &str) -> Option<Transaction> {
+        self.transactions
+            .iter()
+            .find(|transaction| transaction.id == id)
+            .cloned()
+    }
+
+    // Transactions created in `[since, until)`, newest first like Up Bank's,
+    // optionally only for one account.
+    pub fn transactions(
+        &self,
+        account_id: Option<&str>,
+        since: &str,
+        until: &str,
+    ) -> Vec<Transaction> {
+        let (Some(since), Some(until)) = (parse_utc(since), parse_utc(until)) else {
+            return Vec::new();
+        };
+        self.transactions
+            .iter()
+            .filter(|transaction| {
+                account_id.is_none_or(|id| id == transaction.account_id)
+                    && parse_utc(&transaction.date)
+                        .is_some_and(|date| since <= date && date < until)
+            })
+            .cloned()
+            .collect()
+    }
+}
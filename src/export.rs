@@ -0,0 +1,389 @@
+use actix_web::error::InternalError;
+use actix_web::http::header::{
+    ContentDisposition, ContentEncoding, DispositionParam, DispositionType,
+};
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use printpdf::{BuiltinFont, Line, Mm, PdfDocument, PdfLayerReference, Point};
+
+use crate::cache::{CacheKey, TransactionCache};
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::money::{format_base, from_minor_units};
+use crate::up::UpClient;
+use crate::{
+    apply_income_allocations, apply_prior_rollover, budget_totals, categorize_transactions,
+    error_page, exclude_held, fetch_transactions, filter_by_account, month_range,
+    overspent_categories, query_flag, selected_account, selected_month, upstream_error,
+    BudgetCategory, BudgetQuery, Sources, Transaction,
+};
+
+// Quotes a CSV field when it contains a delimiter, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
+
+// Fetches the month selected on the query string with the same account and
+// HELD filtering as the budget page.
+pub async fn selected_transactions(
+    query: &BudgetQuery,
+    sources: Sources<'_>,
+) -> Result<(i32, u32, Vec<Transaction>), Error> {
+    let start_day = sources.config.fiscal_start_day;
+    let (year, month) = selected_month(query, start_day).map_err(|message| {
+        let response = error_page(StatusCode::BAD_REQUEST, "Invalid Month", &message);
+        InternalError::from_response(message, response)
+    })?;
+
+    let key = CacheKey {
+        account_id: None,
+        year,
+        month,
+    };
+    let mut transactions = fetch_transactions(sources, key, query_flag(&query.refresh))
+        .await
+        .map_err(upstream_error)?;
+    filter_by_account(&mut transactions, selected_account(&query.account_id));
+    exclude_held(&mut transactions, query_flag(&query.include_held));
+
+    Ok((year, month, transactions))
+}
+
+pub async fn transactions_csv(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, transactions) = selected_transactions(&query, sources).await?;
+    let categorized = categorize_transactions(
+        transactions,
+        categories.resolve_allocations(year, month),
+        &rules,
+    );
+
+    let mut rows: Vec<_> = categorized
+        .iter()
+        .flat_map(|category| {
+            category
+                .transactions
+                .iter()
+                .map(move |transaction| (transaction, category.name.as_str()))
+        })
+        .collect();
+    rows.sort_by(|a, b| a.0.date.cmp(&b.0.date));
+
+    let mut csv = String::from("id,date,description,amount,category\r\n");
+    for (transaction, category) in rows {
+        csv.push_str(&format!(
+            "{},{},{},{:.2},{}\r\n",
+            csv_field(&transaction.id),
+            csv_field(&transaction.date),
+            csv_field(&transaction.description),
+            transaction.value(),
+            csv_field(category)
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(attachment(format!(
+            "transactions-{}-{:02}.csv",
+            year, month
+        )))
+        .body(csv))
+}
+
+// Escapes the characters OFX's SGML syntax treats specially.
+fn ofx_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// OFX dates are YYYYMMDD; Up Bank's local date is the one shown in the app.
+fn ofx_date(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|date| date.format("%Y%m%d").to_string())
+        .unwrap_or_else(|_| {
+            timestamp
+                .chars()
+                .filter(char::is_ascii_digit)
+                .take(8)
+                .collect()
+        })
+}
+
+// A minimal OFX 1.x bank statement for importing into GnuCash and similar
+// tools. Up Bank transaction ids are used as FITIDs, which keeps re-imports of
+// the same month from creating duplicates.
+pub async fn transactions_ofx(
+    query: web::Query<BudgetQuery>,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, mut transactions) = selected_transactions(&query, sources).await?;
+    transactions.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let (start_date, end_date) = month_range(config.fiscal_start_day, year, month);
+    // Statements have the one currency, so foreign transactions go in
+    // converted to the base currency as they're budgeted
+    let currency = config.base_currency.as_str();
+    let account_id = selected_account(&query.account_id).unwrap_or("UP");
+    // There's no historical balance to report, so the ledger balance is the
+    // month's net movement
+    let net: i64 = transactions
+        .iter()
+        .map(|transaction| transaction.base_amount())
+        .sum();
+    let net = from_minor_units(net, currency);
+    let now = Utc::now().format("%Y%m%d%H%M%S");
+
+    let mut statement = String::new();
+    for transaction in &transactions {
+        // OFX 1.x limits NAME to 32 characters
+        let name: String = transaction.description.chars().take(32).collect();
+        statement.push_str(&format!(
+            "<STMTTRN>\r\n<TRNTYPE>{}\r\n<DTPOSTED>{}\r\n<TRNAMT>{:.2}\r\n<FITID>{}\r\n<NAME>{}\r\n</STMTTRN>\r\n",
+            if transaction.amount < 0 { "DEBIT" } else { "CREDIT" },
+            ofx_date(transaction.settled_at.as_deref().unwrap_or(&transaction.date)),
+            from_minor_units(transaction.base_amount(), currency),
+            ofx_text(&transaction.id),
+            ofx_text(&name)
+        ));
+    }
+
+    let ofx = format!(
+        "OFXHEADER:100\r\nDATA:OFXSGML\r\nVERSION:102\r\nSECURITY:NONE\r\nENCODING:USASCII\r\nCHARSET:1252\r\nCOMPRESSION:NONE\r\nOLDFILEUID:NONE\r\nNEWFILEUID:NONE\r\n\r\n\
+<OFX>\r\n\
+<SIGNONMSGSRSV1>\r\n<SONRS>\r\n<STATUS>\r\n<CODE>0\r\n<SEVERITY>INFO\r\n</STATUS>\r\n<DTSERVER>{now}\r\n<LANGUAGE>ENG\r\n</SONRS>\r\n</SIGNONMSGSRSV1>\r\n\
+<BANKMSGSRSV1>\r\n<STMTTRNRS>\r\n<TRNUID>1\r\n<STATUS>\r\n<CODE>0\r\n<SEVERITY>INFO\r\n</STATUS>\r\n\
+<STMTRS>\r\n<CURDEF>{currency}\r\n\
+<BANKACCTFROM>\r\n<BANKID>UP\r\n<ACCTID>{account}\r\n<ACCTTYPE>CHECKING\r\n</BANKACCTFROM>\r\n\
+<BANKTRANLIST>\r\n<DTSTART>{start}\r\n<DTEND>{end}\r\n{statement}</BANKTRANLIST>\r\n\
+<LEDGERBAL>\r\n<BALAMT>{net:.2}\r\n<DTASOF>{end}\r\n</LEDGERBAL>\r\n\
+</STMTRS>\r\n</STMTTRNRS>\r\n</BANKMSGSRSV1>\r\n</OFX>\r\n",
+        now = now,
+        currency = ofx_text(currency),
+        account = ofx_text(account_id),
+        start = ofx_date(&start_date),
+        end = ofx_date(&end_date),
+        statement = statement,
+        net = net,
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ofx")
+        .insert_header(attachment(format!(
+            "transactions-{}-{:02}.ofx",
+            year, month
+        )))
+        .body(ofx))
+}
+
+// A4 page size and layout for the PDF report, in millimetres.
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const LINE_HEIGHT: f32 = 7.0;
+// Left edge of the name column and right-aligned edges of the amount columns
+const AMOUNT_COLUMNS: [f32; 3] = [120.0, 150.0, 180.0];
+
+// `format_base` with a hyphen for the minus sign the builtin fonts lack.
+fn pdf_amount(amount: i64, base: &str) -> String {
+    format_base(amount, base).replace('\u{2212}', "-")
+}
+
+// Renders the month's budget summary as a one-table PDF, continuing onto
+// further pages when there are more categories than fit on one. Amounts are
+// in the base currency `base`.
+fn budget_pdf(
+    title: &str,
+    categories: &[BudgetCategory],
+    base: &str,
+) -> Result<Vec<u8>, printpdf::Error> {
+    let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+    layer.use_text(title, 18.0, Mm(MARGIN), Mm(y), &bold);
+    y -= LINE_HEIGHT * 2.0;
+
+    // Builtin fonts have no metrics to hand, so amounts are right-aligned by
+    // estimating Helvetica's digit width at 10pt
+    let write_row = |layer: &PdfLayerReference, y: f32, cells: [&str; 4], font| {
+        layer.use_text(cells[0], 10.0, Mm(MARGIN), Mm(y), font);
+        for (cell, right) in cells[1..].iter().zip(AMOUNT_COLUMNS) {
+            let x = right - cell.chars().count() as f32 * 1.96;
+            layer.use_text(*cell, 10.0, Mm(x), Mm(y), font);
+        }
+    };
+    let rule = |layer: &PdfLayerReference, y: f32| {
+        layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(MARGIN), Mm(y)), false),
+                (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(y)), false),
+            ],
+            is_closed: false,
+        });
+    };
+
+    let header = ["Category", "Allocated", "Spent", "Remaining"];
+    write_row(&layer, y, header, &bold);
+    rule(&layer, y - 2.0);
+    y -= LINE_HEIGHT;
+
+    for category in categories {
+        if y < MARGIN + LINE_HEIGHT {
+            let (page, next) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+            layer = doc.get_page(page).get_layer(next);
+            y = PAGE_HEIGHT - MARGIN;
+            write_row(&layer, y, header, &bold);
+            rule(&layer, y - 2.0);
+            y -= LINE_HEIGHT;
+        }
+        let over = category.spent_amount > category.allocated_amount;
+        write_row(
+            &layer,
+            y,
+            [
+                &category.name,
+                &pdf_amount(category.allocated_amount, base),
+                &pdf_amount(category.spent_amount, base),
+                &pdf_amount(category.remaining_amount(), base),
+            ],
+            if over { &bold } else { &regular },
+        );
+        y -= LINE_HEIGHT;
+    }
+
+    let (allocated, spent, remaining) = budget_totals(categories);
+    rule(&layer, y + LINE_HEIGHT - 2.0);
+    write_row(
+        &layer,
+        y,
+        [
+            "Total",
+            &pdf_amount(allocated, base),
+            &pdf_amount(spent, base),
+            &pdf_amount(remaining, base),
+        ],
+        &bold,
+    );
+    y -= LINE_HEIGHT * 2.0;
+
+    let overspent = overspent_categories(categories);
+    if !overspent.is_empty() {
+        if y < MARGIN + LINE_HEIGHT * (overspent.len() + 1) as f32 {
+            let (page, next) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+            layer = doc.get_page(page).get_layer(next);
+            y = PAGE_HEIGHT - MARGIN;
+        }
+        layer.use_text("Over Budget", 12.0, Mm(MARGIN), Mm(y), &bold);
+        y -= LINE_HEIGHT;
+        for (name, over) in overspent {
+            layer.use_text(
+                format!("{} is {} over budget", name, pdf_amount(over, base)),
+                10.0,
+                Mm(MARGIN),
+                Mm(y),
+                &regular,
+            );
+            y -= LINE_HEIGHT;
+        }
+    }
+
+    doc.save_to_bytes()
+}
+
+// The budget page's summary as a printable PDF.
+pub async fn budget_report_pdf(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, transactions) = selected_transactions(&query, sources).await?;
+    let mut categorized = categorize_transactions(
+        transactions,
+        categories.resolve_allocations(year, month),
+        &rules,
+    );
+    apply_income_allocations(&mut categorized);
+    if selected_account(&query.account_id).is_none() {
+        apply_prior_rollover(&mut categorized, &db, categories.rollover(), year, month);
+    }
+
+    let month_label = NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|date| date.format("%B %Y").to_string())
+        .unwrap_or_else(|| format!("{}/{}", month, year));
+    let pdf = budget_pdf(
+        &format!("Budget Report - {}", month_label),
+        &categorized,
+        &config.base_currency,
+    )
+    .map_err(|e| {
+        let response = error_page(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Report Error",
+            "The PDF report could not be generated.",
+        );
+        InternalError::from_response(e, response)
+    })?;
+
+    // PDF content is compressed already, so it is sent as it is
+    Ok(HttpResponse::Ok()
+        .content_type("application/pdf")
+        .insert_header(ContentEncoding::Identity)
+        .insert_header(attachment(format!("budget-{}-{:02}.pdf", year, month)))
+        .body(pdf))
+}
@@ -0,0 +1,102 @@
+use chrono_tz::Tz;
+use std::collections::HashMap;
+
+use crate::cache::AccountInfo;
+use crate::{BudgetCategory, Transaction};
+
+// One line of the double-entry journal: `amount` moves from
+// `credit_account` into `debit_account`, so every entry balances by
+// construction. Accounts are named like "Assets:Spending" or
+// "Expenses:Groceries".
+pub struct LedgerEntry {
+    pub date: String,
+    pub description: String,
+    pub debit_account: String,
+    pub credit_account: String,
+    // Always positive; the direction is given by the two accounts
+    pub amount: f64,
+    pub currency: String,
+    // The receipt attached to the transaction in the Up app, if any
+    pub attachment_id: Option<String>,
+}
+
+// The journal entry for a single transaction. Spending debits the expense
+// category and credits the bank account it came out of; income debits the
+// bank account and credits where it came from. Transfers between the user's
+// own accounts go through a "Transfers" account so each side still balances
+// when only one account's transactions are shown. Dates are shown in `tz`.
+// `None` for zero amounts, which move nothing.
+pub fn ledger_entry(
+    transaction: &Transaction,
+    category: &str,
+    account: &str,
+    tz: Tz,
+) -> Option<LedgerEntry> {
+    if transaction.amount == 0 {
+        return None;
+    }
+    let bank = format!("Assets:{}", account);
+    let counter = if transaction.is_transfer {
+        "Assets:Transfers".to_string()
+    } else if transaction.amount > 0 {
+        format!("Income:{}", transaction.description)
+    } else {
+        format!("Expenses:{}", category)
+    };
+    let (debit_account, credit_account) = if transaction.amount > 0 {
+        (bank, counter)
+    } else {
+        (counter, bank)
+    };
+    Some(LedgerEntry {
+        date: transaction.display_date(tz),
+        description: transaction.description.clone(),
+        debit_account,
+        credit_account,
+        amount: transaction.value().abs(),
+        currency: transaction.currency.clone(),
+        attachment_id: transaction.attachment_id.clone(),
+    })
+}
+
+// Journal entries for `transactions`, in the same order, naming expense
+// accounts after the budget category each transaction was sorted into and
+// bank accounts by their display name where known. A transaction split
+// across categories gets one entry per share.
+pub fn ledger_entries(
+    transactions: &[Transaction],
+    categories: &[BudgetCategory],
+    accounts: &[AccountInfo],
+    tz: Tz,
+) -> Vec<LedgerEntry> {
+    let mut shares: HashMap<&str, Vec<(&Transaction, &str)>> = HashMap::new();
+    for category in categories {
+        for transaction in &category.transactions {
+            shares
+                .entry(transaction.id.as_str())
+                .or_default()
+                .push((transaction, category.name.as_str()));
+        }
+    }
+    let account_name = |id: &str| {
+        accounts
+            .iter()
+            .find(|account| account.id == id)
+            .map(|account| account.display_name.clone())
+            .unwrap_or_else(|| id.to_string())
+    };
+
+    transactions
+        .iter()
+        .flat_map(|transaction| {
+            let account = account_name(&transaction.account_id);
+            let parts = shares
+                .get(transaction.id.as_str())
+                .cloned()
+                .unwrap_or_else(|| vec![(transaction, "Uncategorized")]);
+            parts
+                .into_iter()
+                .filter_map(move |(part, category)| ledger_entry(part, category, &account, tz))
+        })
+        .collect()
+}
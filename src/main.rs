@@ -1,420 +1,1693 @@
-use actix_files::Files;
-use actix_web::{get, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
-use chrono::{Datelike, Utc};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderValue, LOCATION, RETRY_AFTER};
+use actix_web::http::{KeepAlive, StatusCode};
+use actix_web::middleware::{from_fn, Compress, Logger};
+use actix_web::{web, App, Error, HttpResponse, HttpServer};
+use askama::Template;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
 use dotenv::dotenv;
-use reqwest::Client;
+use log::{error, info, warn};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::boxed::Box;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::time::Duration;
+
+mod alerts;
+mod api;
+mod auth;
+mod cache;
+mod calendar;
+mod chart;
+mod config;
+mod db;
+mod demo;
+mod export;
+mod ledger;
+mod merchants;
+mod metrics;
+mod money;
+mod networth;
+mod pagination;
+mod report;
+mod review;
+mod rules;
+mod subscriptions;
+mod timeout;
+mod up;
+mod webhook;
+mod weekly;
+mod ytd;
+
+use cache::{AccountCache, AccountInfo, CacheKey, TransactionCache};
+use config::{BudgetConfig, Config, CurrencyRates, Rollover, Rules};
+use db::Database;
+use ledger::{ledger_entries, LedgerEntry};
+use money::{filters, from_minor_units};
+use pagination::Pagination;
+use up::{Account, UpClient, UpError};
+
+// How a category's budget is set: a fixed amount in minor units of the base
+// currency, or a percentage of the month's incoming money.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Allocation {
+    Fixed(i64),
+    Percent(f64),
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 struct BudgetCategory {
     name: String,
-    allocated_amount: f64,
-    spent_amount: f64,
+    allocation: Allocation,
+    // Amounts are in minor units of the base currency, like
+    // `Transaction::amount`. Percentage allocations are only known once the
+    // month's income is, see `apply_income_allocations`
+    allocated_amount: i64,
+    spent_amount: i64,
+    income_amount: i64,
+    // Carried over from last month and already included in
+    // `allocated_amount`, see `apply_rollover`
+    rollover_amount: i64,
     transactions: Vec<Transaction>,
+    // Receives transactions no rule or tag places elsewhere
+    #[serde(skip)]
+    is_fallback: bool,
+    // Card header colour (a CSS hex colour) and Font Awesome icon class;
+    // defaults are picked when unset, see `color` and `icon`
+    #[serde(skip)]
+    color: Option<String>,
+    #[serde(skip)]
+    icon: Option<String>,
+}
+
+// Colours given to categories that don't configure one, picked by name so a
+// category keeps its colour from month to month.
+const CATEGORY_COLORS: &[&str] = &[
+    "#007bff", "#28a745", "#fd7e14", "#6f42c1", "#20c997", "#e83e8c", "#17a2b8", "#ffc107",
+];
+const DEFAULT_ICON: &str = "fa-tag";
+
+impl BudgetCategory {
+    fn new(name: &str, allocation: Allocation) -> Self {
+        BudgetCategory {
+            name: name.to_string(),
+            allocation,
+            allocated_amount: match allocation {
+                Allocation::Fixed(amount) => amount,
+                Allocation::Percent(_) => 0,
+            },
+            spent_amount: 0,
+            income_amount: 0,
+            rollover_amount: 0,
+            transactions: Vec::new(),
+            is_fallback: false,
+            color: None,
+            icon: None,
+        }
+    }
+
+    fn with_icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_string());
+        self
+    }
+
+    fn color(&self) -> &str {
+        self.color.as_deref().unwrap_or_else(|| {
+            let hash = self.name.bytes().map(usize::from).sum::<usize>();
+            CATEGORY_COLORS[hash % CATEGORY_COLORS.len()]
+        })
+    }
+
+    fn icon(&self) -> &str {
+        self.icon.as_deref().unwrap_or(DEFAULT_ICON)
+    }
+
+    fn remaining_amount(&self) -> i64 {
+        self.allocated_amount - self.spent_amount
+    }
+
+    // Debits count towards spending; credits such as refunds or salary are
+    // tracked as income so they don't inflate the spent figure.
+    fn add_transaction(&mut self, transaction: Transaction) {
+        let amount = transaction.base_amount();
+        if amount < 0 {
+            self.spent_amount += amount.abs();
+        } else {
+            self.income_amount += amount;
+        }
+        self.transactions.push(transaction);
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Transaction {
+    id: String,
+    // When the transaction was created, as an RFC 3339 timestamp
     date: String,
+    // When it settled; `None` while HELD
+    #[serde(default)]
+    settled_at: Option<String>,
     description: String,
-    amount: f64,
+    // In the currency's minor units, e.g. cents, as Up Bank's
+    // `valueInBaseUnits`. Use `value` for the amount in whole units.
+    amount: i64,
+    currency: String,
+    status: String,
+    account_id: String,
+    // Amount moved to a saver by Up Bank's round-up feature, if any, in
+    // minor units like `amount`
+    #[serde(default)]
+    round_up: Option<i64>,
+    // `amount` in minor units of the base currency, or `None` when no rate is
+    // configured for `currency`. Computed on fetch rather than stored.
+    #[serde(default)]
+    converted_amount: Option<i64>,
+    #[serde(default)]
+    tags: Vec<String>,
+    // Money moved between the user's own accounts rather than spent or earned
+    #[serde(default)]
+    is_transfer: bool,
+    // Id of the category Up Bank put the transaction in, like "groceries"
+    #[serde(default)]
+    up_category: Option<String>,
+    // Id of the receipt attached in the Up app, if any
+    #[serde(default)]
+    attachment_id: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct Balance {
-    currencyCode: String,
-    value: String,
+impl Transaction {
+    // The settlement time when there is one, otherwise the creation time.
+    fn display_date(&self, tz: Tz) -> String {
+        format_date(self.settled_at.as_deref().unwrap_or(&self.date), tz)
+    }
+
+    // `amount` in whole units of its own currency, e.g. dollars.
+    fn value(&self) -> f64 {
+        from_minor_units(self.amount, &self.currency)
+    }
+
+    // The amount to count towards budgets, in minor units of the base
+    // currency. Unconverted foreign amounts are still counted as-is rather
+    // than dropped, and flagged on the page.
+    fn base_amount(&self) -> i64 {
+        self.converted_amount.unwrap_or(self.amount)
+    }
+
+    // `amount` in its own currency, formatted by `format_amount`.
+    fn signed_amount(&self, base: &str) -> (String, &'static str) {
+        format_amount(self.amount, &self.currency, base)
+    }
+
+    // `base_amount` formatted by `format_amount`.
+    fn signed_base_amount(&self, base: &str) -> (String, &'static str) {
+        format_amount(self.base_amount(), base, base)
+    }
+
+    // The `percent` share of the transaction counted towards one category of
+    // a split, e.g. "Woolworths (70%)" for $70 of a $100 shop. Shares are
+    // rounded to the nearest minor unit.
+    fn split(&self, percent: f64) -> Transaction {
+        let share = percent / 100.0;
+        let share_of = |units: i64| (units as f64 * share).round() as i64;
+        let mut part = self.clone();
+        part.description = format!("{} ({}%)", self.description, percent);
+        part.amount = share_of(self.amount);
+        part.converted_amount = self.converted_amount.map(share_of);
+        part.round_up = self.round_up.map(share_of);
+        part
+    }
+
+    // Whether the transaction is big enough, either way, to be highlighted,
+    // see `Config::large_txn_threshold`.
+    fn is_large(&self, threshold: Option<i64>) -> bool {
+        threshold.is_some_and(|threshold| self.base_amount().abs() >= threshold)
+    }
 }
 
-#[derive(Deserialize)]
-struct AccountAttributes {
-    displayName: String,
-    balance: Balance,
+#[derive(Debug, Clone, Deserialize)]
+struct CategoryRule {
+    category: String,
+    keywords: Vec<String>,
+    // Treat `keywords` as regular expressions, matched case-insensitively
+    // against the raw description, instead of whole words
+    #[serde(default)]
+    regex: bool,
+    // `keywords` compiled when `regex` is set, by `load_rules`
+    #[serde(skip)]
+    patterns: Vec<Regex>,
 }
 
-#[derive(Deserialize)]
-struct Account {
-    id: String,
-    attributes: AccountAttributes,
+impl CategoryRule {
+    // The first keyword, or regex, matching the description. `words` is the
+    // description after `normalize_words`.
+    fn matching_keyword(&self, description: &str, words: &str) -> Option<&str> {
+        let keyword = if self.regex {
+            self.keywords
+                .iter()
+                .zip(&self.patterns)
+                .find(|(_, pattern)| pattern.is_match(description))
+                .map(|(keyword, _)| keyword)
+        } else {
+            self.keywords
+                .iter()
+                .find(|keyword| contains_words(words, keyword))
+        };
+        keyword.map(String::as_str)
+    }
 }
 
-#[derive(Deserialize)]
-struct AccountsResponse {
-    data: Vec<Account>,
+// Spreads a transaction over several categories, such as a supermarket run
+// that is part groceries and part household, from `[[splits]]` in the rules
+// file. Matches one transaction by id, or any whose description contains
+// `description` as whole words, like rule keywords.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SplitRule {
+    #[serde(default)]
+    transaction_id: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    // Percentage of the amount for each category, adding up to 100
+    shares: BTreeMap<String, f64>,
+}
+
+impl SplitRule {
+    fn matches(&self, transaction: &Transaction, words: &str) -> bool {
+        match (&self.transaction_id, &self.description) {
+            (Some(id), _) => *id == transaction.id,
+            (None, Some(description)) => contains_words(words, description),
+            (None, None) => false,
+        }
+    }
 }
 
-fn get_budget_categories() -> Vec<BudgetCategory> {
+// The built-in categories, with allocations in the base currency `base`.
+fn get_budget_categories(base: &str) -> Vec<BudgetCategory> {
+    let fixed = |amount: f64| Allocation::Fixed(money::to_minor_units(amount, base));
     vec![
-        BudgetCategory {
-            name: "Groceries".to_string(),
-            allocated_amount: 500.0,
-            spent_amount: 0.0,
-            transactions: Vec::new(),
-        },
-        BudgetCategory {
-            name: "Transportation".to_string(),
-            allocated_amount: 200.0,
-            spent_amount: 0.0,
-            transactions: Vec::new(),
-        },
-        BudgetCategory {
-            name: "Entertainment".to_string(),
-            allocated_amount: 150.0,
-            spent_amount: 0.0,
-            transactions: Vec::new(),
-        },
-        BudgetCategory {
-            name: "Utilities".to_string(),
-            allocated_amount: 300.0,
-            spent_amount: 0.0,
-            transactions: Vec::new(),
-        },
-        BudgetCategory {
-            name: "Dining Out".to_string(),
-            allocated_amount: 250.0,
-            spent_amount: 0.0,
-            transactions: Vec::new(),
-        },
+        BudgetCategory::new("Groceries", fixed(500.0)).with_icon("fa-shopping-cart"),
+        BudgetCategory::new("Transportation", fixed(200.0)).with_icon("fa-car"),
+        BudgetCategory::new("Entertainment", fixed(150.0)).with_icon("fa-film"),
+        BudgetCategory::new("Utilities", fixed(300.0)).with_icon("fa-bolt"),
+        BudgetCategory::new("Dining Out", fixed(250.0)).with_icon("fa-utensils"),
         // Add more categories as needed
     ]
 }
 
-async fn fetch_transactions(api_key: &str) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+fn get_category_rules() -> Vec<CategoryRule> {
+    let rule = |category: &str, keywords: &[&str]| CategoryRule {
+        category: category.to_string(),
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        regex: false,
+        patterns: Vec::new(),
+    };
+
+    vec![
+        rule("Groceries", &["woolworths", "coles", "aldi"]),
+        rule("Transportation", &["uber", "lyft", "bus", "train"]),
+        rule("Entertainment", &["netflix", "spotify", "cinema"]),
+        rule("Utilities", &["electricity", "water", "internet", "phone"]),
+        rule(
+            "Dining Out",
+            &["restaurant", "cafe", "bar", "mcdonalds", "kfc"],
+        ),
+    ]
+}
+
+#[derive(Deserialize, Default)]
+struct BudgetQuery {
+    year: Option<i32>,
+    month: Option<u32>,
+    window: Option<String>,
+    account_id: Option<String>,
+    include_held: Option<String>,
+    refresh: Option<String>,
+    // Comma-separated category names to narrow the page to
+    only: Option<String>,
+    // Which named set of budget categories to use, on `/budget`
+    template: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct AccountQuery {
+    account_id: Option<String>,
+    refresh: Option<String>,
+    q: Option<String>,
+    include_transfers: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+// Percent-encodes text for use as a query string value or path segment.
+fn encode_query_value(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b',' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Escapes text for use in HTML content or a quoted attribute value.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// A paperclip linking to the transaction's receipt, or nothing without one.
+fn attachment_link(transaction: &Transaction) -> String {
+    match &transaction.attachment_id {
+        Some(id) => format!(
+            " <a href=\"/attachments/{}\" title=\"View receipt\" target=\"_blank\" rel=\"noopener\"><i class=\"fas fa-paperclip\"></i></a>",
+            encode_query_value(id)
+        ),
+        None => String::new(),
+    }
+}
+
+// Boolean query parameters such as `?refresh=1` (which bypasses the
+// transaction cache).
+fn query_flag(value: &Option<String>) -> bool {
+    matches!(value.as_deref(), Some("1") | Some("true"))
+}
+
+// The `?account_id=` query parameter, treating an empty value as absent.
+fn selected_account(account_id: &Option<String>) -> Option<&str> {
+    account_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+// Drops pending (HELD) transactions unless asked to keep them. They may still
+// change amount or disappear, so by default totals only reflect settled money.
+fn exclude_held(transactions: &mut Vec<Transaction>, include_held: bool) {
+    if !include_held {
+        transactions.retain(|transaction| transaction.status != "HELD");
+    }
+}
+
+// Drops transfers between the user's own accounts unless asked to keep them;
+// they would otherwise count once as spending and again as income.
+fn exclude_transfers(transactions: &mut Vec<Transaction>, include_transfers: bool) {
+    if !include_transfers {
+        transactions.retain(|transaction| !transaction.is_transfer);
+    }
+}
+
+// Keeps only the transactions belonging to `account_id`, if one was given.
+fn filter_by_account(transactions: &mut Vec<Transaction>, account_id: Option<&str>) {
+    if let Some(account_id) = account_id {
+        transactions.retain(|transaction| transaction.account_id == account_id);
+    }
+}
+
+fn previous_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
+
+fn next_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+// The date the budget period for `(year, month)` starts on, given the day of
+// the month periods start on, `Config::fiscal_start_day`. A start day past
+// the end of a short month is clamped to its last day, so day 31 starts
+// February's period on the 28th or 29th.
+fn period_start(start_day: u32, year: i32, month: u32) -> NaiveDate {
+    let (next_year, next_month) = next_month(year, month);
+    let last_day = NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|next| next.pred_opt())
+        .map_or(28, |last| last.day());
+    NaiveDate::from_ymd_opt(year, month, start_day.min(last_day)).unwrap_or_default()
+}
+
+// The `(year, month)` of the budget period containing `date`. Before the start
+// day that is still the previous month's period.
+fn period_of(start_day: u32, date: DateTime<Utc>) -> (i32, u32) {
+    let (year, month) = (date.year(), date.month());
+    if date.date_naive() < period_start(start_day, year, month) {
+        previous_month(year, month)
+    } else {
+        (year, month)
+    }
+}
+
+// The budget period containing today.
+fn current_period(start_day: u32) -> (i32, u32) {
+    period_of(start_day, Utc::now())
+}
+
+// `since..until` covering the budget period for the given month, which runs
+// from midnight UTC on its start day up to the start day of the next month.
+fn period_bounds(start_day: u32, year: i32, month: u32) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (end_year, end_month) = next_month(year, month);
+    (
+        period_start(start_day, year, month)
+            .and_time(NaiveTime::MIN)
+            .and_utc(),
+        period_start(start_day, end_year, end_month)
+            .and_time(NaiveTime::MIN)
+            .and_utc(),
+    )
+}
+
+// `since..until` for the budget period containing today.
+fn current_month_range(start_day: u32) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (year, month) = current_period(start_day);
+    period_bounds(start_day, year, month)
+}
+
+// `period_bounds` as RFC 3339 strings, as Up Bank and the database take them.
+fn month_range(start_day: u32, year: i32, month: u32) -> (String, String) {
+    let (start_date, end_date) = period_bounds(start_day, year, month);
+    let format = "%Y-%m-%dT%H:%M:%SZ";
+    (
+        start_date.format(format).to_string(),
+        end_date.format(format).to_string(),
+    )
+}
+
+// Resolves the month requested on the query string, defaulting to the current
+// budget period for any missing part.
+fn selected_month(query: &BudgetQuery, start_day: u32) -> Result<(i32, u32), String> {
     let now = Utc::now();
-    let current_year = now.year();
-    let current_month = now.month();
+    let (current_year, current_month) = current_period(start_day);
+    let year = query.year.unwrap_or(current_year);
+    let month = query.month.unwrap_or(current_month);
+
+    if !(1..=12).contains(&month) {
+        return Err(format!("Month must be between 1 and 12, got {}.", month));
+    }
+    if !(2000..=now.year() + 1).contains(&year) {
+        return Err(format!(
+            "Year must be between 2000 and {}, got {}.",
+            now.year() + 1,
+            year
+        ));
+    }
 
-    // Start date: first day of the current month
-    let start_date = format!("{}-{:02}-01T00:00:00Z", current_year, current_month);
+    Ok((year, month))
+}
+
+// Renders an RFC 3339 timestamp like "3 Mar 2024, 2:15 pm" in the display
+// timezone `tz`, falling back to the raw string when it doesn't parse.
+fn format_date(iso: &str, tz: Tz) -> String {
+    DateTime::parse_from_rfc3339(iso)
+        .map(|date| {
+            date.with_timezone(&tz)
+                .format("%-d %b %Y, %-I:%M %P")
+                .to_string()
+        })
+        .unwrap_or_else(|_| iso.to_string())
+}
 
-    // End date: first day of the next month
-    let end_date = if current_month == 12 {
-        format!("{}-01-01T00:00:00Z", current_year + 1)
+// Renders a signed amount, in minor units of `currency`, the same way
+// everywhere: money in as "+$12.50" in green and money out as "−$12.50" in
+// red, with the Bootstrap class to colour it by. `base` is the base currency.
+fn format_amount(units: i64, currency: &str, base: &str) -> (String, &'static str) {
+    let amount = from_minor_units(units, currency);
+    let (sign, class) = if units > 0 {
+        ("+", "text-success")
+    } else if units < 0 {
+        ("\u{2212}", "text-danger")
     } else {
-        format!("{}-{:02}-01T00:00:00Z", current_year, current_month + 1)
+        ("", "text-muted")
     };
+    (
+        format!(
+            "{}{}",
+            sign,
+            money::format_money(amount.abs(), currency, base)
+        ),
+        class,
+    )
+}
+
+// The rolling windows offered instead of calendar months, as `?window=30d`.
+const WINDOW_DAYS: &[u32] = &[7, 30, 90];
+
+fn parse_window(window: &str) -> Result<u32, String> {
+    window
+        .trim()
+        .strip_suffix('d')
+        .and_then(|days| days.parse().ok())
+        .filter(|days| WINDOW_DAYS.contains(days))
+        .ok_or_else(|| {
+            format!(
+                "Window must be one of 7d, 30d or 90d, got \"{}\".",
+                escape_html(window)
+            )
+        })
+}
+
+// Everything the fetch helpers need, borrowed from the app data: where
+// transactions come from, and the settings and rules deciding which are kept.
+#[derive(Clone, Copy)]
+struct Sources<'a> {
+    up: &'a UpClient,
+    cache: &'a TransactionCache,
+    db: &'a Database,
+    rates: &'a CurrencyRates,
+    config: &'a Config,
+    rules: &'a Rules,
+}
 
-    let client = Client::new();
+// Transactions from the last `days` days.
+async fn fetch_window(
+    sources: Sources<'_>,
+    days: u32,
+    refresh: bool,
+) -> Result<Vec<Transaction>, UpError> {
+    let now = Utc::now();
+    let since = now - chrono::Duration::days(days.into());
+    fetch_range(sources, since, now, refresh).await
+}
+
+// Transactions created in `since..until`, assembled from the budget periods
+// the range overlaps so the cache and database are reused.
+async fn fetch_range(
+    sources: Sources<'_>,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    refresh: bool,
+) -> Result<Vec<Transaction>, UpError> {
+    let start_day = sources.config.fiscal_start_day;
+    // `until` is exclusive, so a range ending right at a period's start stops
+    // at the period before
+    let last = period_of(start_day, until - chrono::Duration::nanoseconds(1));
     let mut transactions = Vec::new();
-    let mut next_page_url = Some(format!(
-        "https://api.up.com.au/api/v1/transactions?filter[since]={}&filter[until]={}&page[size]=100",
-        start_date, end_date
-    ));
+    let (mut year, mut month) = period_of(start_day, since);
+    loop {
+        let key = CacheKey {
+            account_id: None,
+            year,
+            month,
+        };
+        transactions.extend(fetch_transactions(sources, key, refresh).await?);
+        if (year, month) >= last {
+            break;
+        }
+        (year, month) = next_month(year, month);
+    }
 
-    while let Some(url) = next_page_url {
-        let response = client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", api_key))
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            let json: Value = response.json().await?;
-            if let Some(data) = json["data"].as_array() {
-                for item in data {
-                    let amount_str = item["attributes"]["amount"]["value"]
-                        .as_str()
-                        .unwrap_or("0.00");
-                    let amount: f64 = amount_str.parse().unwrap_or(0.0);
-
-                    let transaction = Transaction {
-                        date: item["attributes"]["createdAt"]
-                            .as_str()
-                            .unwrap_or("")
-                            .to_string(),
-                        description: item["attributes"]["description"]
-                            .as_str()
-                            .unwrap_or("")
-                            .to_string(),
-                        amount,
-                    };
-                    transactions.push(transaction);
-                }
-                next_page_url = json["links"]["next"].as_str().map(|s| s.to_string());
-            } else {
-                break;
+    transactions.retain(|transaction| {
+        DateTime::parse_from_rfc3339(&transaction.date).is_ok_and(|date| {
+            let date = date.with_timezone(&Utc);
+            date >= since && date < until
+        })
+    });
+    Ok(transactions)
+}
+
+// Keeps the first occurrence of each transaction id. Up Bank's pages can
+// overlap when a transaction arrives mid-fetch, which would double count it.
+fn dedupe_by_id(transactions: &mut Vec<Transaction>) {
+    let mut seen = HashSet::new();
+    transactions.retain(|transaction| seen.insert(transaction.id.clone()));
+}
+
+// Returns the month's transactions described by `key`, leaving out ignored
+// descriptions and accounts excluded by `Config::account_filter`. A key
+// naming one account is an explicit choice, so it is shown even when that
+// account is excluded from budgets.
+async fn fetch_transactions(
+    sources: Sources<'_>,
+    key: CacheKey,
+    refresh: bool,
+) -> Result<Vec<Transaction>, UpError> {
+    let whole_month = key.account_id.is_none();
+    let mut transactions = fetch_month(sources, key, refresh).await?;
+    let ignored = &sources.rules.ignore;
+    transactions.retain(|transaction| !ignored.ignores(&transaction.description));
+    if whole_month {
+        let filter = &sources.config.account_filter;
+        transactions.retain(|transaction| filter.allows(&transaction.account_id));
+    }
+    Ok(transactions)
+}
+
+// The month's transactions described by `key`, from the cache when a fresh
+// entry exists, from the database for fully synced past months, and from Up
+// Bank otherwise. Everything fetched from Up Bank is persisted.
+async fn fetch_month(
+    sources: Sources<'_>,
+    key: CacheKey,
+    refresh: bool,
+) -> Result<Vec<Transaction>, UpError> {
+    let Sources {
+        up,
+        cache,
+        db,
+        rates,
+        config,
+        ..
+    } = sources;
+    if !refresh {
+        if let Some(transactions) = cache.get(&key) {
+            return Ok(transactions);
+        }
+    }
+    // Taken before fetching, so a webhook landing mid-fetch isn't lost
+    // under a listing that predates it
+    let generation = cache.generation(key.year, key.month);
+
+    // Only fetches across all accounts cover the whole month, so only they
+    // can be served from or marked as synced in the database
+    let whole_month = key.account_id.is_none();
+
+    if whole_month && !refresh {
+        match db
+            .is_month_complete(key.year, key.month)
+            .and_then(|complete| {
+                complete
+                    .then(|| db.load_transactions(key.year, key.month))
+                    .transpose()
+            }) {
+            Ok(Some(mut transactions)) => {
+                transactions
+                    .iter_mut()
+                    .for_each(|t| rates.convert_transaction(t));
+                cache.insert(key, generation, transactions.clone());
+                return Ok(transactions);
             }
+            Ok(None) => {}
+            Err(e) => warn!("failed to load stored transactions: {}", e),
+        }
+    }
+
+    let (start_date, end_date) = month_range(config.fiscal_start_day, key.year, key.month);
+    let mut transactions = match &key.account_id {
+        Some(account_id) => {
+            up.list_account_transactions(account_id, &start_date, &end_date)
+                .await?
+        }
+        None => up.list_transactions(&start_date, &end_date).await?,
+    };
+    dedupe_by_id(&mut transactions);
+
+    let persisted = db.save_transactions(&transactions).and_then(|()| {
+        if whole_month {
+            db.mark_synced(key.year, key.month)
         } else {
-            let error_message = format!(
-                "Failed to fetch transactions: {}",
-                response.text().await.unwrap_or_default()
-            );
-            return Err(error_message.into());
+            Ok(())
         }
+    });
+    if let Err(e) = persisted {
+        warn!("failed to store transactions: {}", e);
     }
 
+    transactions
+        .iter_mut()
+        .for_each(|t| rates.convert_transaction(t));
+
+    cache.insert(key, generation, transactions.clone());
     Ok(transactions)
 }
 
+// Lowercases text and collapses every run of whitespace or punctuation into a
+// single space, so "Corner Bar & Grill" becomes "corner bar grill".
+fn normalize_words(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Whether `phrase` appears in `words` as whole words. Both sides must already
+// be normalized with `normalize_words`.
+fn contains_words(words: &str, phrase: &str) -> bool {
+    let bytes = words.as_bytes();
+    words.match_indices(phrase).any(|(start, _)| {
+        let end = start + phrase.len();
+        (start == 0 || bytes[start - 1] == b' ') && (end == words.len() || bytes[end] == b' ')
+    })
+}
+
+// Why `categorize` puts a transaction where it does.
+enum CategoryMatch<'a> {
+    // Shared out between categories by a split rule
+    Split(&'a SplitRule),
+    // A tag named like the budget category
+    Tag(String),
+    // Up Bank's own category, mapped to a budget category by
+    // `[up_categories]`
+    UpCategory { id: String, category: String },
+    // The first matching rule, and the keyword or regex that matched
+    Rule(&'a CategoryRule, &'a str),
+    // Nothing matched, so the fallback category
+    Unmatched,
+}
+
+impl CategoryMatch<'_> {
+    // The budget category to add the whole transaction to, `None` for splits
+    // and the fallback.
+    fn category(&self) -> Option<&str> {
+        match self {
+            CategoryMatch::Tag(category) | CategoryMatch::UpCategory { category, .. } => {
+                Some(category)
+            }
+            CategoryMatch::Rule(rule, _) => Some(&rule.category),
+            CategoryMatch::Split(_) | CategoryMatch::Unmatched => None,
+        }
+    }
+}
+
+// Works out where a transaction belongs, checking in order: split rules, tags,
+// Up Bank's category, then the keyword rules. `words` is the description
+// after `normalize_words`.
+fn match_category<'a>(
+    transaction: &Transaction,
+    words: &str,
+    budget_categories: &[BudgetCategory],
+    rules: &'a Rules,
+) -> CategoryMatch<'a> {
+    // A split rule shares the amount out between its categories
+    if let Some(split) = rules
+        .splits
+        .iter()
+        .find(|split| split.matches(transaction, words))
+    {
+        return CategoryMatch::Split(split);
+    }
+
+    // A tag named like a budget category wins over keyword matching
+    if let Some(category) = transaction.tags.iter().find_map(|tag| {
+        budget_categories
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(tag))
+    }) {
+        return CategoryMatch::Tag(category.name.clone());
+    }
+
+    // Then Up Bank's own category, when it's mapped to a budget category
+    if let Some((id, category)) = transaction
+        .up_category
+        .as_ref()
+        .and_then(|id| rules.up_categories.get(id).map(|category| (id, category)))
+    {
+        return CategoryMatch::UpCategory {
+            id: id.clone(),
+            category: category.clone(),
+        };
+    }
+
+    // Otherwise match whole words of the description against the rule
+    // keywords, so "bar" doesn't match "Barber", or the rule's regexes;
+    // first matching rule wins
+    rules
+        .categories
+        .iter()
+        .find_map(|rule| {
+            rule.matching_keyword(&transaction.description, words)
+                .map(|keyword| CategoryMatch::Rule(rule, keyword))
+        })
+        .unwrap_or(CategoryMatch::Unmatched)
+}
+
+// Sorts the transactions into copies of the budget categories, leaving both
+// inputs untouched so callers can keep using the flat list.
+fn categorize(
+    transactions: &[Transaction],
+    budget_categories: &[BudgetCategory],
+    rules: &Rules,
+) -> Vec<BudgetCategory> {
+    let mut budget_categories = budget_categories.to_vec();
+    for transaction in transactions {
+        let description_words = normalize_words(&transaction.description);
+        match match_category(transaction, &description_words, &budget_categories, rules) {
+            CategoryMatch::Split(split) => {
+                for (name, percent) in &split.shares {
+                    add_to_category(
+                        &mut budget_categories,
+                        Some(name),
+                        transaction.split(*percent),
+                    );
+                }
+            }
+            matched => add_to_category(
+                &mut budget_categories,
+                matched.category(),
+                transaction.clone(),
+            ),
+        }
+    }
+
+    budget_categories
+}
+
+// Adds the transaction to the budget category called `name`, falling back to
+// the configured fallback category when nothing matched or the rule names an
+// unknown one.
+fn add_to_category(
+    budget_categories: &mut Vec<BudgetCategory>,
+    name: Option<&str>,
+    transaction: Transaction,
+) {
+    let index = name
+        .and_then(|name| budget_categories.iter().position(|c| c.name == name))
+        .or_else(|| budget_categories.iter().position(|c| c.is_fallback));
+    match index {
+        Some(index) => {
+            budget_categories[index].add_transaction(transaction);
+        }
+        None => {
+            // Without a configured fallback, create "Other" on first use
+            let mut other_category = BudgetCategory::new("Other", Allocation::Fixed(0));
+            other_category.is_fallback = true;
+            other_category.add_transaction(transaction);
+            budget_categories.push(other_category);
+        }
+    }
+}
+
+// Consuming form of `categorize`.
 fn categorize_transactions(
     transactions: Vec<Transaction>,
-    mut budget_categories: Vec<BudgetCategory>,
+    budget_categories: Vec<BudgetCategory>,
+    rules: &Rules,
 ) -> Vec<BudgetCategory> {
-    for transaction in transactions {
-        let description_lower = transaction.description.to_lowercase();
+    categorize(&transactions, &budget_categories, rules)
+}
 
-        // Match transaction descriptions to categories
-        let category = if description_lower.contains("woolworths")
-            || description_lower.contains("coles")
-            || description_lower.contains("aldi")
-        {
-            "Groceries"
-        } else if description_lower.contains("uber")
-            || description_lower.contains("lyft")
-            || description_lower.contains("bus")
-            || description_lower.contains("train")
-        {
-            "Transportation"
-        } else if description_lower.contains("netflix")
-            || description_lower.contains("spotify")
-            || description_lower.contains("cinema")
-        {
-            "Entertainment"
-        } else if description_lower.contains("electricity")
-            || description_lower.contains("water")
-            || description_lower.contains("internet")
-            || description_lower.contains("phone")
-        {
-            "Utilities"
-        } else if description_lower.contains("restaurant")
-            || description_lower.contains("cafe")
-            || description_lower.contains("bar")
-            || description_lower.contains("mcdonalds")
-            || description_lower.contains("kfc")
-        {
-            "Dining Out"
-        } else {
-            "Other"
+// Turns percentage allocations into amounts now that the month's income (all
+// credits, across every category) is known, rounded to the nearest minor unit.
+fn apply_income_allocations(categories: &mut [BudgetCategory]) {
+    let income: i64 = categories.iter().map(|c| c.income_amount).sum();
+    for category in categories {
+        if let Allocation::Percent(percent) = category.allocation {
+            category.allocated_amount = (income as f64 * percent / 100.0).round() as i64;
+        }
+    }
+}
+
+// Adds what each category carried over from last month to its allocation.
+// Must run after `apply_income_allocations`, which resets percentage
+// allocations.
+fn apply_rollover(categories: &mut [BudgetCategory], prior_remaining: &[(String, i64)]) {
+    for (name, amount) in prior_remaining {
+        if let Some(category) = categories.iter_mut().find(|c| &c.name == name) {
+            category.rollover_amount = *amount;
+            category.allocated_amount += amount;
+        }
+    }
+}
+
+// Shorthand for the rollover step every whole-budget view of a month shares
+// with `/budget`, so their allocated and remaining figures agree.
+fn apply_prior_rollover(
+    categories: &mut [BudgetCategory],
+    db: &Database,
+    rollover: Rollover,
+    year: i32,
+    month: u32,
+) {
+    apply_rollover(categories, &prior_remaining(db, rollover, year, month));
+}
+
+// What rolls into `(year, month)` from the previous month's snapshot. Months
+// are only snapshotted when their budget page is viewed, so there is nothing
+// to carry from a month that never was.
+fn prior_remaining(db: &Database, rollover: Rollover, year: i32, month: u32) -> Vec<(String, i64)> {
+    if rollover == Rollover::Off {
+        return Vec::new();
+    }
+    let (prev_year, prev_month) = previous_month(year, month);
+    match db.load_remaining(prev_year, prev_month) {
+        Ok(remaining) => remaining
+            .into_iter()
+            .map(|(name, amount)| (name, rollover.carried(amount)))
+            .filter(|(_, amount)| *amount != 0)
+            .collect(),
+        Err(e) => {
+            warn!("failed to load last month's budget for rollover: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Total allocated, total spent and overall remaining across all categories.
+fn budget_totals(categories: &[BudgetCategory]) -> (i64, i64, i64) {
+    let allocated: i64 = categories.iter().map(|c| c.allocated_amount).sum();
+    let spent: i64 = categories.iter().map(|c| c.spent_amount).sum();
+    (allocated, spent, allocated - spent)
+}
+
+// How much of a category's allocation has been spent, as a percentage. A
+// category with nothing allocated is 0% used until it spends anything, and
+// 100% used after that.
+fn percent_used(category: &BudgetCategory) -> f64 {
+    if category.allocated_amount > 0 {
+        category.spent_amount as f64 / category.allocated_amount as f64 * 100.0
+    } else if category.spent_amount > 0 {
+        100.0
+    } else {
+        0.0
+    }
+}
+
+// Total saved through round-ups, in minor units, and how many transactions
+// were rounded up. Up Bank only rounds up in the account's own currency.
+fn round_up_total(transactions: &[Transaction]) -> (i64, usize) {
+    transactions
+        .iter()
+        .filter_map(|transaction| transaction.round_up)
+        .fold((0, 0), |(total, count), units| (total + units, count + 1))
+}
+
+const SPARKLINE_WIDTH: f64 = 120.0;
+const SPARKLINE_HEIGHT: f64 = 24.0;
+
+// SVG polyline points tracing a category's cumulative spending, bucketed by
+// day in the display timezone, over the `days` days starting at `start`.
+// Without any spending the line is flat along the bottom.
+fn sparkline_points(category: &BudgetCategory, start: NaiveDate, days: u32, tz: Tz) -> String {
+    let days = days.max(1) as usize;
+
+    let mut daily = vec![0; days];
+    for transaction in &category.transactions {
+        let amount = transaction.base_amount();
+        if amount >= 0 {
+            continue;
+        }
+        let Ok(date) = DateTime::parse_from_rfc3339(&transaction.date) else {
+            continue;
         };
+        let day = (date.with_timezone(&tz).date_naive() - start).num_days();
+        if let Ok(day) = usize::try_from(day) {
+            if day < days {
+                daily[day] += amount.abs();
+            }
+        }
+    }
+
+    let mut cumulative = 0;
+    let totals: Vec<i64> = daily
+        .iter()
+        .map(|spent| {
+            cumulative += spent;
+            cumulative
+        })
+        .collect();
+    let max = cumulative.max(1) as f64;
+    let step = SPARKLINE_WIDTH / (days.max(2) - 1) as f64;
+
+    totals
+        .iter()
+        .enumerate()
+        .map(|(day, total)| {
+            let y = SPARKLINE_HEIGHT - *total as f64 / max * SPARKLINE_HEIGHT;
+            format!("{:.1},{:.1}", day as f64 * step, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Every category that has spent more than it was allocated, paired with the
+// amount it went over by.
+fn overspent_categories(categories: &[BudgetCategory]) -> Vec<(String, i64)> {
+    categories
+        .iter()
+        .filter(|category| category.spent_amount > category.allocated_amount)
+        .map(|category| {
+            (
+                category.name.clone(),
+                category.spent_amount - category.allocated_amount,
+            )
+        })
+        .collect()
+}
+
+// One category's spending this month against the month before.
+struct CategoryComparison {
+    name: String,
+    current: i64,
+    previous: i64,
+    delta: i64,
+}
+
+// Pairs each current category with the same-named category from the previous
+// month, treating categories that didn't exist then as having spent nothing.
+fn month_over_month(
+    current: &[BudgetCategory],
+    previous: &[BudgetCategory],
+) -> Vec<CategoryComparison> {
+    current
+        .iter()
+        .map(|category| {
+            let previous = previous
+                .iter()
+                .find(|c| c.name == category.name)
+                .map_or(0, |c| c.spent_amount);
+            CategoryComparison {
+                name: category.name.clone(),
+                current: category.spent_amount,
+                previous,
+                delta: category.spent_amount - previous,
+            }
+        })
+        .collect()
+}
+
+// The budget template shown on the budget page, from `?template=`, and the
+// others the page can switch to.
+struct TemplateChoice {
+    selected: String,
+    names: Vec<String>,
+    is_default: bool,
+}
+
+// The `?only=` filter as applied to the budget page.
+#[derive(Default)]
+struct CategoryFocus {
+    // Categories still shown; empty when the page isn't narrowed
+    shown: Vec<String>,
+    // Requested names that match no category
+    ignored: Vec<String>,
+}
+
+// Keeps only the categories named in `only`, along with their comparisons.
+// Names are matched ignoring case; unknown ones are reported rather than
+// rejected, and if none match the page is left as it was.
+fn focus_categories(
+    categories: &mut Vec<BudgetCategory>,
+    comparisons: &mut Vec<CategoryComparison>,
+    only: &str,
+) -> CategoryFocus {
+    let mut focus = CategoryFocus::default();
+    for name in only
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match categories
+            .iter()
+            .find(|category| category.name.eq_ignore_ascii_case(name))
+        {
+            Some(category) if !focus.shown.contains(&category.name) => {
+                focus.shown.push(category.name.clone())
+            }
+            Some(_) => {}
+            None => focus.ignored.push(name.to_string()),
+        }
+    }
+
+    if !focus.shown.is_empty() {
+        categories.retain(|category| focus.shown.contains(&category.name));
+        comparisons.retain(|comparison| focus.shown.contains(&comparison.name));
+    }
+    focus
+}
+
+#[derive(Template)]
+#[template(path = "budget.html")]
+struct BudgetTemplate<'a> {
+    categories: &'a [BudgetCategory],
+    // Amounts are in minor units of the base currency
+    overspent: Vec<(String, i64)>,
+    total_allocated: i64,
+    total_spent: i64,
+    total_remaining: i64,
+    month_label: String,
+    year: i32,
+    month: u32,
+    prev_year: i32,
+    prev_month: u32,
+    next_year: i32,
+    next_month: u32,
+    include_held: bool,
+    filter_params: String,
+    window: Option<u32>,
+    window_days: &'static [u32],
+    // Sparkline points for each entry in `categories`
+    sparklines: Vec<String>,
+    round_up_total: i64,
+    round_up_count: usize,
+    // Comparison with the previous month for each entry in `categories`;
+    // empty when viewing a rolling window
+    comparisons: Vec<CategoryComparison>,
+    focus: CategoryFocus,
+    // `filter_params` without the `only` filter, for showing every category
+    unfocused_params: String,
+    // Inline SVG of allocated against spent per category
+    chart: String,
+    // Applies to every category's transaction table at once
+    pagination: Pagination,
+    // Pages needed for the longest category
+    page_count: usize,
+    template: TemplateChoice,
+    config: &'a Config,
+}
+
+// Renders a template into an HTML response, turning template errors into a
+// 500 page rather than a panic. The config is passed to the template filters
+// as runtime values.
+fn render_template<T: Template>(template: &T, config: &Config) -> HttpResponse {
+    match template.render_with_values(config) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(body),
+        Err(e) => error_page(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Rendering Error",
+            &format!("Failed to render page: {}", e),
+        ),
+    }
+}
+
+async fn render_budget_page(
+    budget_categories: Vec<BudgetCategory>,
+    (year, month): (i32, u32),
+    (query, config): (&BudgetQuery, &Config),
+    window: Option<u32>,
+    (round_up_total, round_up_count): (i64, usize),
+    comparisons: Vec<CategoryComparison>,
+    (focus, template): (CategoryFocus, TemplateChoice),
+) -> HttpResponse {
+    let account_id = selected_account(&query.account_id);
+    let include_held = query_flag(&query.include_held);
+    let month_label = match window {
+        Some(days) => format!("Last {} Days", days),
+        None => NaiveDate::from_ymd_opt(year, month, 1)
+            .map(|date| date.format("%B %Y").to_string())
+            .unwrap_or_else(|| format!("{}/{}", month, year)),
+    };
+    let (prev_year, prev_month) = previous_month(year, month);
+    let (next_year, next_month) = next_month(year, month);
+    // Keep the account filter and window when following links on the page
+    let mut filter_params = account_id
+        .map(|id| format!("&account_id={}", encode_query_value(id)))
+        .unwrap_or_default();
+    if let Some(days) = window {
+        filter_params.push_str(&format!("&window={}d", days));
+    }
+    if !template.is_default {
+        filter_params.push_str(&format!(
+            "&template={}",
+            encode_query_value(&template.selected)
+        ));
+    }
+    let unfocused_params = filter_params.clone();
+    if !focus.shown.is_empty() {
+        filter_params.push_str(&format!(
+            "&only={}",
+            encode_query_value(&focus.shown.join(","))
+        ));
+    }
 
-        // Find the matching budget category and add the transaction
-        if let Some(budget_category) = budget_categories.iter_mut().find(|c| c.name == category) {
-            budget_category.spent_amount += transaction.amount.abs();
-            budget_category.transactions.push(transaction);
-        } else {
-            // If category not found, add it under "Other"
-            if let Some(other_category) = budget_categories.iter_mut().find(|c| c.name == "Other") {
-                other_category.spent_amount += transaction.amount.abs();
-                other_category.transactions.push(transaction);
-            } else {
-                // Create "Other" category if it doesn't exist
-                budget_categories.push(BudgetCategory {
-                    name: "Other".to_string(),
-                    allocated_amount: 0.0,
-                    spent_amount: transaction.amount.abs(),
-                    transactions: vec![transaction],
-                });
-            }
+    let (total_allocated, total_spent, total_remaining) = budget_totals(&budget_categories);
+
+    let tz = config.display_tz;
+    let start_day = config.fiscal_start_day;
+    let (start, days) = match window {
+        Some(days) => (
+            Utc::now().with_timezone(&tz).date_naive()
+                - chrono::Duration::days(i64::from(days) - 1),
+            days,
+        ),
+        None => {
+            let first = period_start(start_day, year, month);
+            let days = (period_start(start_day, next_year, next_month) - first).num_days() as u32;
+            (first, days)
         }
-    }
+    };
+    let sparklines = budget_categories
+        .iter()
+        .map(|category| sparkline_points(category, start, days, tz))
+        .collect();
 
-    budget_categories
+    let pagination = Pagination::new(query.page, query.per_page);
+    let page_count = budget_categories
+        .iter()
+        .map(|category| pagination.page_count(category.transactions.len()))
+        .max()
+        .unwrap_or(1);
+
+    render_template(
+        &BudgetTemplate {
+            categories: &budget_categories,
+            overspent: overspent_categories(&budget_categories),
+            total_allocated,
+            total_spent,
+            total_remaining,
+            month_label,
+            year,
+            month,
+            prev_year,
+            prev_month,
+            next_year,
+            next_month,
+            include_held,
+            filter_params,
+            window,
+            window_days: WINDOW_DAYS,
+            sparklines,
+            round_up_total,
+            round_up_count,
+            comparisons,
+            focus,
+            unfocused_params,
+            chart: chart::budget_chart(&budget_categories, &config.base_currency),
+            pagination,
+            page_count,
+            template,
+            config,
+        },
+        config,
+    )
 }
 
-async fn render_budget_page(budget_categories: Vec<BudgetCategory>) -> HttpResponse {
-    let mut categories_html = String::new();
+// Snapshots a month's categorized totals, keeping history beyond what is
+// convenient to re-fetch from Up Bank.
+fn record_budget(db: &Database, year: i32, month: u32, categories: &[BudgetCategory]) {
+    if let Err(e) = db.save_budget(year, month, categories) {
+        warn!("failed to store budget snapshot: {}", e);
+    }
+}
 
-    for category in budget_categories {
-        let remaining_amount = category.allocated_amount - category.spent_amount;
-        let remaining_class = if remaining_amount >= 0.0 {
-            "text-success"
-        } else {
-            "text-danger"
-        };
+async fn budget_page(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month) = match selected_month(&query, config.fiscal_start_day) {
+        Ok(selected) => selected,
+        Err(message) => {
+            return Ok(error_page(
+                StatusCode::BAD_REQUEST,
+                "Invalid Month",
+                &message,
+            ))
+        }
+    };
 
-        let mut transactions_html = String::new();
-        for transaction in category.transactions {
-            transactions_html.push_str(&format!(
-                "<tr>
-                    <td>{}</td>
-                    <td>{}</td>
-                    <td>${:.2}</td>
-                </tr>",
-                transaction.date, transaction.description, transaction.amount
-            ));
+    let window = match query.window.as_deref().map(parse_window).transpose() {
+        Ok(window) => window,
+        Err(message) => {
+            return Ok(error_page(
+                StatusCode::BAD_REQUEST,
+                "Invalid Window",
+                &message,
+            ))
         }
+    };
 
-        categories_html.push_str(&format!(
-            "<div class=\"card mb-4\">
-                <div class=\"card-header\">
-                    <h4>{}</h4>
-                </div>
-                <div class=\"card-body\">
-                    <p>Allocated Amount: <strong>${:.2}</strong></p>
-                    <p>Spent Amount: <strong>${:.2}</strong></p>
-                    <p>Remaining Amount: <strong class=\"{}\">${:.2}</strong></p>
-                    <button class=\"btn btn-link\" type=\"button\" data-toggle=\"collapse\" data-target=\"#collapse-{}\" aria-expanded=\"false\" aria-controls=\"collapse-{}\">
-                        View Transactions
-                    </button>
-                    <div class=\"collapse\" id=\"collapse-{}\">
-                        <div class=\"table-responsive\">
-                            <table class=\"table table-striped\">
-                                <thead>
-                                    <tr>
-                                        <th>Date</th>
-                                        <th>Description</th>
-                                        <th>Amount</th>
-                                    </tr>
-                                </thead>
-                                <tbody>
-                                    {}
-                                </tbody>
-                            </table>
-                        </div>
-                    </div>
-                </div>
-            </div>",
-            category.name,
-            category.allocated_amount,
-            category.spent_amount,
-            remaining_class,
-            remaining_amount,
-            category.name.replace(" ", "-"),
-            category.name.replace(" ", "-"),
-            category.name.replace(" ", "-"),
-            transactions_html
+    let selected = query
+        .template
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(categories.default_template())
+        .to_string();
+    let template = TemplateChoice {
+        is_default: selected == categories.default_template(),
+        names: categories.template_names(),
+        selected,
+    };
+    let Some(budget_categories) = categories.resolve_template(&template.selected, year, month)
+    else {
+        return Ok(error_page(
+            StatusCode::BAD_REQUEST,
+            "Unknown Budget Template",
+            &format!(
+                "There is no budget template named \"{}\". The templates are: {}.",
+                escape_html(&template.selected),
+                escape_html(&template.names.join(", "))
+            ),
         ));
+    };
+
+    let account_id = selected_account(&query.account_id);
+    let include_held = query_flag(&query.include_held);
+    let refresh = query_flag(&query.refresh);
+    let transactions_result = match window {
+        Some(days) => fetch_window(sources, days, refresh).await,
+        None => {
+            let key = CacheKey {
+                account_id: None,
+                year,
+                month,
+            };
+            fetch_transactions(sources, key, refresh).await
+        }
+    };
+
+    // Rate limits, timeouts and a rejected API key each get their own status
+    // and page, so only genuine server faults show up as 5xx from this app
+    let mut transactions = transactions_result.map_err(upstream_error)?;
+    filter_by_account(&mut transactions, account_id);
+    exclude_held(&mut transactions, include_held);
+    let round_ups = round_up_total(&transactions);
+    let mut categorized_budget = categorize_transactions(transactions, budget_categories, &rules);
+    if let Some(days) = window {
+        // Fixed allocations are monthly; prorate them to the window
+        for category in &mut categorized_budget {
+            category.allocated_amount =
+                (category.allocated_amount as f64 * f64::from(days) / 30.0).round() as i64;
+        }
     }
+    apply_income_allocations(&mut categorized_budget);
+    // Rollover follows the recorded whole-budget history, so it only
+    // applies to the same unfiltered calendar-month view of the default
+    // template that records it
+    if account_id.is_none() && window.is_none() && template.is_default {
+        apply_prior_rollover(
+            &mut categorized_budget,
+            &db,
+            categories.rollover(),
+            year,
+            month,
+        );
+        record_budget(&db, year, month, &categorized_budget);
+    }
+
+    // Compare against the previous month through the same pipeline.
+    // Past months are served from the cache or database, so this
+    // rarely reaches Up Bank; a failure just hides the comparison.
+    let mut comparisons = match window {
+        Some(_) => Vec::new(),
+        None => {
+            let (prev_year, prev_month) = previous_month(year, month);
+            let key = CacheKey {
+                account_id: None,
+                year: prev_year,
+                month: prev_month,
+            };
+            match fetch_transactions(sources, key, false).await {
+                Ok(mut previous) => {
+                    filter_by_account(&mut previous, account_id);
+                    exclude_held(&mut previous, include_held);
+                    let previous = categorize_transactions(
+                        previous,
+                        categories
+                            .resolve_template(&template.selected, prev_year, prev_month)
+                            .unwrap_or_default(),
+                        &rules,
+                    );
+                    month_over_month(&categorized_budget, &previous)
+                }
+                Err(e) => {
+                    warn!("failed to fetch the previous month for comparison: {}", e);
+                    Vec::new()
+                }
+            }
+        }
+    };
+
+    // Narrowing to `?only=` happens after categorization so each
+    // category still holds exactly the transactions it otherwise would
+    let focus = match &query.only {
+        Some(only) => focus_categories(&mut categorized_budget, &mut comparisons, only),
+        None => CategoryFocus::default(),
+    };
+
+    Ok(render_budget_page(
+        categorized_budget,
+        (year, month),
+        (&query, &config),
+        window,
+        round_ups,
+        comparisons,
+        (focus, template),
+    )
+    .await)
+}
 
-    let html_body = format!(
+fn error_page(status: StatusCode, title: &str, message: &str) -> HttpResponse {
+    let body = format!(
         "<!DOCTYPE html>
         <html lang=\"en\">
         <head>
             <meta charset=\"UTF-8\">
             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-            <title>Monthly Budget Overview</title>
-            <link rel=\"stylesheet\" href=\"https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css\">
-            <script src=\"https://code.jquery.com/jquery-3.5.1.slim.min.js\"></script>
-            <script src=\"https://cdn.jsdelivr.net/npm/bootstrap@4.5.2/dist/js/bootstrap.bundle.min.js\"></script>
+            <title>{}</title>
+            <link href=\"https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css\" rel=\"stylesheet\">
         </head>
         <body>
             <nav class=\"navbar navbar-expand-lg navbar-light bg-light\">
-                <a class=\"navbar-brand\" href=\"#\">My Bank App</a>
-                <div class=\"collapse navbar-collapse\" id=\"navbarNav\">
-                    <ul class=\"navbar-nav\">
-                        <li class=\"nav-item\">
-                            <a class=\"nav-link\" href=\"/\">Home</a>
-                        </li>
-                        <li class=\"nav-item active\">
-                            <a class=\"nav-link\" href=\"/budget\">Budget <span class=\"sr-only\">(current)</span></a>
-                        </li>
-                    </ul>
-                </div>
+                <a href=\"/\" class=\"navbar-brand\">My Bank App</a>
             </nav>
-            <div class=\"container my-5\">
-                <h1 class=\"mb-4\">Monthly Budget Overview</h1>
-                {}
+            <div class=\"container\">
+                <h1 class=\"my-4\">{}</h1>
+                <div class=\"alert alert-danger\">{}</div>
+                <a href=\"/\" class=\"btn btn-primary\">Back to Home</a>
             </div>
-            <footer class=\"footer mt-auto py-3 bg-light\">
-                <div class=\"container\">
-                    <span class=\"text-muted\">Powered by My Bank App.</span>
-                </div>
-            </footer>
         </body>
         </html>",
-        categories_html
+        title, title, message
     );
 
-    HttpResponse::Ok()
+    HttpResponse::build(status)
         .content_type("text/html; charset=utf-8")
-        .body(html_body)
+        .body(body)
 }
 
-async fn budget_page() -> Result<HttpResponse, Error> {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("UP_BANK_API_KEY must be set");
+// Tells the user Up Bank is throttling us, passing its `Retry-After` on so
+// browsers and scripts know when to come back.
+fn rate_limited_page(retry_after: Option<Duration>) -> HttpResponse {
+    let message = match retry_after {
+        Some(delay) => format!(
+            "Up Bank is rate limiting requests. Try again in {} seconds.",
+            delay.as_secs()
+        ),
+        None => "Up Bank is rate limiting requests. Try again in a moment.".to_string(),
+    };
+    let mut response = error_page(StatusCode::TOO_MANY_REQUESTS, "Rate Limited", &message);
+    if let Some(delay) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(delay.as_secs()));
+    }
+    response
+}
+
+// Turns a failed Up Bank request into an error page, so handlers can use `?`
+// instead of panicking when the API is unreachable or returns unexpected data.
+fn upstream_error(e: UpError) -> Error {
+    let response = if let Some(retry_after) = e.rate_limit() {
+        rate_limited_page(retry_after)
+    } else if e.is_timeout() {
+        error_page(
+            StatusCode::GATEWAY_TIMEOUT,
+            "Up Bank Timed Out",
+            "The Up Bank API took too long to respond. Please try again in a moment.",
+        )
+    } else if e.is_unreachable() {
+        error_page(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Up Bank Unavailable",
+            "Could not reach the Up Bank API. Please try again in a moment.",
+        )
+    } else if e.is_unauthorized() {
+        error_page(
+            StatusCode::UNAUTHORIZED,
+            "Invalid or Expired API Key",
+            "Up Bank rejected the API key this app is using. Generate a personal access \
+             token at <a href=\"https://api.up.com.au/getting_started\">api.up.com.au</a>, \
+             set it as <code>API_KEY</code> in the environment or <code>.env</code> file, \
+             then restart the app.",
+        )
+    } else if e.is_truncated() {
+        error_page(
+            StatusCode::BAD_GATEWAY,
+            "Too Many Transactions",
+            "Up Bank had more pages of transactions than <code>UP_MAX_PAGES</code> allows, \
+             so the listing would be incomplete. Raise <code>UP_MAX_PAGES</code> and restart \
+             the app.",
+        )
+    } else {
+        error_page(
+            StatusCode::BAD_GATEWAY,
+            "Up Bank Error",
+            &format!("Unexpected response from the Up Bank API: {}", e),
+        )
+    };
+
+    InternalError::from_response(e, response).into()
+}
+
+// Figures for the home page, fetched live from Up Bank.
+struct LandingSummary {
+    // Balance across every account, per currency
+    balances: BTreeMap<String, f64>,
+    // This period's incoming less outgoing, per currency, in its minor units
+    net: BTreeMap<String, i64>,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct LandingTemplate<'a> {
+    month_label: String,
+    // `None` when Up Bank couldn't be reached; the page still links onwards
+    summary: Option<LandingSummary>,
+    config: &'a Config,
+}
+
+async fn landing_summary(
+    sources: Sources<'_>,
+    account_cache: &AccountCache,
+) -> Result<LandingSummary, UpError> {
+    let (since, until) = current_month_range(sources.config.fiscal_start_day);
+    let (accounts, transactions) = futures::future::join(
+        live_accounts(sources.up, account_cache),
+        fetch_range(sources, since, until, false),
+    )
+    .await;
+
+    let mut balances = BTreeMap::new();
+    for account in accounts? {
+        let balance = &account.attributes.balance;
+        *balances.entry(balance.currency_code.clone()).or_default() += balance.amount();
+    }
+
+    // Counted the same way as the expenses page
+    let mut transactions = transactions?;
+    exclude_held(&mut transactions, false);
+    exclude_transfers(&mut transactions, false);
+    let net = position_totals(&transactions)
+        .into_iter()
+        .map(|(currency, (expenses, incoming))| (currency, incoming - expenses))
+        .collect();
 
-    let transactions_result = fetch_transactions(&api_key).await;
+    Ok(LandingSummary { balances, net })
+}
 
-    match transactions_result {
-        Ok(transactions) => {
-            let budget_categories = get_budget_categories();
-            let categorized_budget = categorize_transactions(transactions, budget_categories);
-            Ok(render_budget_page(categorized_budget).await)
+async fn landing_page(
+    (up, account_cache): (web::Data<UpClient>, web::Data<AccountCache>),
+    cache: web::Data<TransactionCache>,
+    db: web::Data<Database>,
+    rates: web::Data<CurrencyRates>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> HttpResponse {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let summary = match landing_summary(sources, &account_cache).await {
+        Ok(summary) => Some(summary),
+        Err(e) => {
+            warn!("home page summary unavailable: {}", e);
+            None
         }
-        Err(e) => Ok(HttpResponse::InternalServerError()
-            .content_type("text/html; charset=utf-8")
-            .body(format!("<h1>Error Fetching Transactions</h1><p>{}</p>", e))),
-    }
-}
-
-async fn landing_page() -> impl Responder {
-    let body = r#"
-    <!DOCTYPE html>
-    <html lang="en">
-    <head>
-        <meta charset="UTF-8">
-        <meta name="viewport" content="width=device-width, initial-scale=1.0">
-        <title>Welcome to My Bank App</title>
-        <link href="https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css" rel="stylesheet">
-    </head>
-    <body>
-        <nav class="navbar navbar-expand-lg navbar-light bg-light">
-            <a href="/" class="navbar-brand">My Bank App</a>
-        </nav>
-        <div class="container text-center">
-            <h1 class="my-4">Welcome to Your Bank Dashboard</h1>
-            <p class="lead">Manage your accounts with ease.</p>
-            <a href="/allbalances" class="btn btn-primary btn-lg">View Balances</a>
-            <a href="/expenses" class="btn btn-primary btn-lg">View Expenses</a>
-            <a href="/accounts" class="btn btn-primary btn-lg">Select Account</a>
-            <a href="/budget" class="btn btn-primary btn-lg">Budget</a>
-            <spacer style="height: 100px;"></spacer>
-        </div>
-        <spacer style="height: 100px;"></spacer>
-        <footer class="footer mt-auto py-3 bg-light">
-        <spacer style="height: 100px;"></spacer>
-            <div class="container">
-                <span class="text-muted">Powered by My Bank App.</span>
-            </div>
-        </footer>
-    </body>
-    </html>
-    "#;
+    };
 
-    actix_web::HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(body)
+    let (year, month) = current_period(config.fiscal_start_day);
+    render_template(
+        &LandingTemplate {
+            month_label: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|date| date.format("%B %Y").to_string())
+                .unwrap_or_else(|| format!("{}/{}", month, year)),
+            summary,
+            config: &config,
+        },
+        &config,
+    )
 }
 
-async fn list_accounts() -> impl Responder {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("API_KEY must be set");
+// Every account's current details, balances included. Always goes to Up
+// Bank, refreshing the cached account list on the way.
+async fn live_accounts(
+    up: &UpClient,
+    account_cache: &AccountCache,
+) -> Result<Vec<Account>, UpError> {
+    let accounts = up.list_accounts().await?;
+    account_cache.insert(&accounts);
+    Ok(accounts)
+}
+
+// Account ids, names and types, from the account cache while it is fresh.
+async fn account_infos(
+    up: &UpClient,
+    account_cache: &AccountCache,
+) -> Result<Vec<AccountInfo>, UpError> {
+    if let Some(accounts) = account_cache.get() {
+        return Ok(accounts);
+    }
+    let accounts = live_accounts(up, account_cache).await?;
+    Ok(accounts.iter().map(AccountInfo::from).collect())
+}
+
+// `/attachments/{id}`: sends the browser on to a receipt attached to a
+// transaction. Up Bank's download links expire, so a fresh one is fetched on
+// each click instead of being stored with the transaction.
+async fn open_attachment(
+    path: web::Path<String>,
+    up: web::Data<UpClient>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Ok(error_page(
+            StatusCode::NOT_FOUND,
+            "Receipt Not Found",
+            "That isn't a valid attachment link.",
+        ));
+    }
+    match up.attachment_url(&id).await.map_err(upstream_error)? {
+        Some(url) => Ok(HttpResponse::Found()
+            .insert_header((LOCATION, url))
+            .finish()),
+        None => Ok(error_page(
+            StatusCode::NOT_FOUND,
+            "Receipt Unavailable",
+            "Up Bank doesn't have a file for this receipt yet. If it was only just \
+             attached, try again in a minute.",
+        )),
+    }
+}
 
-    let client = Client::new();
-    let response = client
-        .get("https://api.up.com.au/api/v1/accounts")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .send()
+async fn list_accounts(
+    up: web::Data<UpClient>,
+    account_cache: web::Data<AccountCache>,
+) -> Result<HttpResponse, Error> {
+    let accounts = account_infos(&up, &account_cache)
         .await
-        .expect("Failed to send request");
+        .map_err(upstream_error)?;
 
     let mut buttons = String::new();
 
-    if response.status().is_success() {
-        let accounts_response: Value = response.json().await.expect("Failed to parse response");
-        if let Some(accounts) = accounts_response["data"].as_array() {
-            for account in accounts {
-                let display_name = account["attributes"]["displayName"]
-                    .as_str()
-                    .unwrap_or("Unknown");
-                let account_id = account["id"].as_str().unwrap_or("Unknown");
-
-                // Create a button for each account
-                buttons.push_str(&format!(
-                    "<form action=\"/balances\" method=\"get\" style=\"display: inline-block; margin: 10px;\">
-                        <input type=\"hidden\" name=\"account_id\" value=\"{}\">
-                        <button type=\"submit\" class=\"btn btn-primary\">{}<br><small>{}</small></button>
-                    </form>",
-                    account_id, display_name, account_id
-                ));
-            }
-        }
-    } else {
-        buttons.push_str("<p>Failed to load accounts.</p>");
+    for account in &accounts {
+        // Create a button for each account
+        buttons.push_str(&format!(
+            "<form action=\"/balances\" method=\"get\" style=\"display: inline-block; margin: 10px;\">
+                <input type=\"hidden\" name=\"account_id\" value=\"{}\">
+                <button type=\"submit\" class=\"btn btn-primary\">{}<br><small>{}</small></button>
+            </form>",
+            account.id, account.display_name, account.id
+        ));
     }
 
     let body = format!(
@@ -445,102 +1718,162 @@ async fn list_accounts() -> impl Responder {
         buttons
     );
 
-    actix_web::HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(body)
+        .body(body))
 }
 
-async fn get_balances(req: HttpRequest) -> impl Responder {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("API_KEY must be set");
-
-    // Extract the account_id from the query parameters
-    let account_id = req
-        .query_string()
-        .split('&')
-        .find_map(|pair| {
-            let mut iter = pair.split('=');
-            if let (Some(key), Some(value)) = (iter.next(), iter.next()) {
-                if key == "account_id" {
-                    return Some(value);
-                }
-            }
-            None
-        })
-        .unwrap_or("");
-
-    // Get the current year and month
-    let now = Utc::now();
-    let current_year = now.year();
-    let current_month = now.month();
+async fn get_balances(
+    query: web::Query<AccountQuery>,
+    (up, account_cache): (web::Data<UpClient>, web::Data<AccountCache>),
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let Some(account_id) = selected_account(&query.account_id) else {
+        return Ok(error_page(
+            StatusCode::BAD_REQUEST,
+            "No Account Selected",
+            "Choose an account from the <a href=\"/accounts\">accounts page</a> to see its transactions.",
+        ));
+    };
 
-    // Format the start and end dates with RFC 3339
-    let start_date = format!("{}-{:02}-01T00:00:00Z", current_year, current_month);
-    let end_date = if current_month == 12 {
-        format!("{}-01-01T00:00:00Z", current_year + 1)
-    } else {
-        format!("{}-{:02}-01T00:00:00Z", current_year, current_month + 1)
+    // Only the name is needed here, so the cached list will do. An account
+    // missing from it may be newer than the cache, so ask Up Bank directly.
+    let cached = account_infos(&up, &account_cache)
+        .await
+        .map_err(upstream_error)?
+        .into_iter()
+        .find(|account| account.id == account_id);
+    let lookup = match cached {
+        Some(account) => Ok(account),
+        None => up
+            .get_account(account_id)
+            .await
+            .map(|account| AccountInfo::from(&account)),
+    };
+    let account = match lookup {
+        Ok(account) => account,
+        Err(e) if e.is_not_found() => {
+            return Ok(error_page(
+                StatusCode::NOT_FOUND,
+                "Account Not Found",
+                &format!(
+                    "There is no account with id \"{}\". Pick one from the <a href=\"/accounts\">accounts page</a>.",
+                    escape_html(account_id)
+                ),
+            ));
+        }
+        Err(e) => return Err(upstream_error(e)),
     };
 
-    let client = Client::new();
-    let mut transactions = Vec::new();
-    let mut next_page_url = Some(format!(
-        "https://api.up.com.au/api/v1/transactions?filter[since]={}&filter[until]={}&filter[status]=SETTLED&page[size]=100",
-        start_date, end_date
-    ));
+    // Get the current budget period
+    let (year, month) = current_period(config.fiscal_start_day);
+    let key = CacheKey {
+        account_id: Some(account.id.clone()),
+        year,
+        month,
+    };
 
-    // Loop to handle pagination
-    while let Some(url) = next_page_url {
-        let response = client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", api_key))
-            .send()
-            .await
-            .expect("Failed to send request");
-
-        if response.status().is_success() {
-            let json: Value = response.json().await.expect("Failed to parse response");
-            if let Some(data) = json["data"].as_array() {
-                for transaction in data {
-                    // Filter transactions by account_id
-                    let transaction_account_id = transaction
-                        .get("relationships")
-                        .and_then(|rel| rel.get("account"))
-                        .and_then(|acc| acc.get("data"))
-                        .and_then(|data| data.get("id"))
-                        .and_then(|id| id.as_str());
-
-                    if transaction_account_id == Some(account_id) {
-                        let description = transaction["attributes"]["description"]
-                            .as_str()
-                            .unwrap_or("Unknown");
-                        let amount = transaction["attributes"]["amount"]["value"]
-                            .as_str()
-                            .unwrap_or("0.00")
-                            .parse::<f64>()
-                            .unwrap_or(0.0);
-                        let date = transaction["attributes"]["createdAt"]
-                            .as_str()
-                            .unwrap_or("Unknown");
-
-                        transactions.push(format!(
-                            "<li class=\"list-group-item\">{} - {} AUD ({})</li>",
-                            date,
-                            amount.abs(),
-                            description
-                        ));
-                    }
-                }
+    let mut account_transactions = fetch_transactions(sources, key, query_flag(&query.refresh))
+        .await
+        .map_err(upstream_error)?;
+    // Narrow down to descriptions containing the search term, ignoring case
+    let search = query.q.as_deref().map(str::trim).unwrap_or("");
+    if !search.is_empty() {
+        let needle = search.to_lowercase();
+        account_transactions
+            .retain(|transaction| transaction.description.to_lowercase().contains(&needle));
+    }
 
-                // Handle pagination by setting next_page_url to the next link or None if there isn't one
-                next_page_url = json["links"]["next"].as_str().map(|s| s.to_string());
+    // Pending (held) transactions already count against the available
+    // balance but may still change, so they are listed apart from settled ones
+    let (pending, settled): (Vec<_>, Vec<_>) = account_transactions
+        .into_iter()
+        .partition(|transaction| transaction.status == "HELD");
+    let row = |transaction: &Transaction, class: &str, badge: &str| {
+        let (amount, amount_class) = transaction.signed_amount(&config.base_currency);
+        format!(
+            "<li class=\"list-group-item{}{}\">{} - <span class=\"{}\">{}</span> ({}){}{}</li>",
+            class,
+            if transaction.is_large(config.large_txn_threshold) {
+                " list-group-item-warning"
             } else {
-                break; // No data, exit the loop
+                ""
+            },
+            transaction.display_date(config.display_tz),
+            amount_class,
+            amount,
+            transaction.description,
+            attachment_link(transaction),
+            badge
+        )
+    };
+    // Pending transactions are few, so only the settled list is paged
+    let pagination = Pagination::new(query.page, query.per_page);
+    let transactions: Vec<String> = pagination
+        .items(&settled)
+        .iter()
+        .map(|transaction| row(transaction, "", ""))
+        .collect();
+    let page_count = pagination.page_count(settled.len());
+    let page_nav = if page_count > 1 {
+        let link = |page: usize, label: &str| {
+            format!(
+                "<a class=\"btn btn-outline-secondary\" href=\"/balances?account_id={}&amp;q={}{}\">{}</a>",
+                encode_query_value(&account.id),
+                encode_query_value(search),
+                escape_html(&pagination.params(page)),
+                label
+            )
+        };
+        format!(
+            "<nav class=\"d-flex justify-content-between align-items-center mt-3\">{}<span class=\"text-muted\">{}</span>{}</nav>",
+            if pagination.page > 1 {
+                link(pagination.page - 1, "&laquo; Newer")
+            } else {
+                "<span></span>".to_string()
+            },
+            pagination.range_label(settled.len()),
+            if pagination.page < page_count {
+                link(pagination.page + 1, "Older &raquo;")
+            } else {
+                "<span></span>".to_string()
             }
-        } else {
-            break; // Stop on any error response
-        }
-    }
+        )
+    } else {
+        String::new()
+    };
+    let pending_section = if pending.is_empty() {
+        String::new()
+    } else {
+        let rows: Vec<String> = pending
+            .iter()
+            .map(|transaction| {
+                row(
+                    transaction,
+                    " text-muted font-italic",
+                    " <span class=\"badge badge-secondary\">Pending</span>",
+                )
+            })
+            .collect();
+        format!(
+            "<h3 class=\"mt-4\">Pending</h3>
+                <p class=\"text-muted\">Held by the bank and not yet settled; amounts may still change.</p>
+                <ul class=\"list-group\">{}</ul>",
+            rows.join("")
+        )
+    };
 
     let body = format!(
         "<!DOCTYPE html>
@@ -548,16 +1881,24 @@ async fn get_balances(req: HttpRequest) -> impl Responder {
         <head>
             <meta charset=\"UTF-8\">
             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-            <title>Transactions for Account {}</title>
+            <title>Transactions for {}</title>
             <link href=\"https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css\" rel=\"stylesheet\">
+            <link href=\"https://cdnjs.cloudflare.com/ajax/libs/font-awesome/5.15.4/css/all.min.css\" rel=\"stylesheet\">
         </head>
         <body>
             <nav class=\"navbar navbar-expand-lg navbar-light bg-light\">
                 <a class=\"navbar-brand\" href=\"#\">My Bank App</a>
             </nav>
             <div class=\"container\">
-                <h1 class=\"my-4\">Transactions for Account {}</h1>
+                <h1 class=\"my-4\">Transactions for {}</h1>
+                <form action=\"/balances\" method=\"get\" class=\"form-inline mb-3\">
+                    <input type=\"hidden\" name=\"account_id\" value=\"{}\">
+                    <input type=\"search\" name=\"q\" value=\"{}\" class=\"form-control mr-2\" placeholder=\"Search descriptions\">
+                    <button type=\"submit\" class=\"btn btn-primary\">Search</button>
+                </form>
                 <ul class=\"list-group\">{}</ul>
+                {}
+                {}
             </div>
         </body>
         <footer class=\"footer mt-auto py-3 bg-light\">
@@ -566,205 +1907,543 @@ async fn get_balances(req: HttpRequest) -> impl Responder {
             </div>
         </footer>
         </html>",
-        account_id, account_id, transactions.join("")
+        account.display_name,
+        account.display_name,
+        escape_html(&account.id),
+        escape_html(search),
+        transactions.join(""),
+        page_nav,
+        pending_section
     );
 
-    actix_web::HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(body)
+        .body(body))
 }
 
-async fn show_balances() -> impl Responder {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("API_KEY must be set");
+#[derive(Template)]
+#[template(path = "balances.html")]
+struct BalancesTemplate<'a> {
+    spending: Vec<&'a Account>,
+    savers: Vec<&'a Account>,
+    // Combined saver balance per currency
+    saver_totals: BTreeMap<String, f64>,
+}
 
-    let client = Client::new();
-    let response = client
-        .get("https://api.up.com.au/api/v1/accounts")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .send()
+async fn show_balances(
+    up: web::Data<UpClient>,
+    account_cache: web::Data<AccountCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    // Balances must be current, so this always fetches
+    let accounts = live_accounts(&up, &account_cache)
         .await
-        .expect("Failed to send request");
+        .map_err(upstream_error)?;
 
-    let accounts_response: AccountsResponse =
-        response.json().await.expect("Failed to parse response");
+    let (savers, spending): (Vec<_>, Vec<_>) = accounts.iter().partition(|a| a.is_saver());
 
-    let balances: Vec<_> = accounts_response
-        .data
-        .iter()
-        .map(|account| {
-            format!(
-                "<li class=\"list-group-item\">Account: {}, Balance: {} {}</li>",
-                account.attributes.displayName,
-                account.attributes.balance.value,
-                account.attributes.balance.currencyCode
-            )
-        })
-        .collect();
+    let mut saver_totals = BTreeMap::new();
+    for account in &savers {
+        let balance = &account.attributes.balance;
+        *saver_totals
+            .entry(balance.currency_code.clone())
+            .or_default() += balance.amount();
+    }
 
-    let body = format!(
-        "<!DOCTYPE html>
-        <html lang=\"en\">
-            <nav class=\"navbar navbar-expand-lg navbar-light bg-light\">
-    <a class=\"navbar-brand\" href=\"#\">My Bank App</a>
-</nav>
-        <head>
-            <meta charset=\"UTF-8\">
-            <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-            <title>Account Balances</title>
-            <link href=\"https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css\" rel=\"stylesheet\">
-        </head>
-        <body>
-            <div class=\"container\">
-                <h1 class=\"my-4\">Your Account Balances</h1>
-                <ul class=\"list-group\">{}</ul>
-            </div>
-        </body>
-        <footer class=\"footer mt-auto py-3 bg-light\">
-    <div class=\"container\">
-        <span class=\"text-muted\">Place sticky footer content here.</span>
-    </div>
-</footer>
-        </html>",
-        balances.join("")
-    );
+    Ok(render_template(
+        &BalancesTemplate {
+            spending,
+            savers,
+            saver_totals,
+        },
+        &config,
+    ))
+}
 
-    actix_web::HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(body)
+// Incoming money from one source, such as an employer or a shop refunding.
+struct IncomeSource {
+    name: String,
+    total: i64,
+    transactions: Vec<Transaction>,
 }
 
-async fn get_expenses() -> impl Responder {
-    dotenv().ok();
-    let api_key = env::var("API_KEY").expect("UP_BANK_API_KEY must be set");
+// Splits transactions into (incoming, outgoing) by the sign of their amount.
+fn split_income(transactions: &[Transaction]) -> (Vec<&Transaction>, Vec<&Transaction>) {
+    transactions
+        .iter()
+        .partition(|transaction| transaction.amount > 0)
+}
 
-    // Get the current year and month
-    let now = Utc::now();
-    let current_year = now.year();
-    let current_month = now.month();
+// Per-currency (expenses, incoming) totals in each currency's minor units.
+// Totals are kept per currency; summing across currencies is meaningless.
+fn position_totals(transactions: &[Transaction]) -> BTreeMap<String, (i64, i64)> {
+    let (incoming, outgoing) = split_income(transactions);
+    let mut totals: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+    for transaction in &outgoing {
+        totals.entry(transaction.currency.clone()).or_default().0 += transaction.amount.abs();
+    }
+    for transaction in &incoming {
+        totals.entry(transaction.currency.clone()).or_default().1 += transaction.amount;
+    }
+    totals
+}
 
-    // Format the start and end dates with RFC 3339
-    let start_date = format!("{}-{:02}-01T00:00:00Z", current_year, current_month);
-    let end_date = if current_month == 12 {
-        format!("{}-01-01T00:00:00Z", current_year + 1)
-    } else {
-        format!("{}-{:02}-01T00:00:00Z", current_year, current_month + 1)
+// Groups incoming transactions by their normalized description, so repeated
+// pay runs or refunds from the same shop add up. Largest source first.
+fn income_sources(income: &[&Transaction]) -> Vec<IncomeSource> {
+    let mut sources: Vec<IncomeSource> = Vec::new();
+    for &transaction in income {
+        let mut name = merchants::merchant_name(&transaction.description);
+        if name.is_empty() {
+            name = transaction.description.clone();
+        }
+        let index = match sources.iter().position(|source| source.name == name) {
+            Some(index) => index,
+            None => {
+                sources.push(IncomeSource {
+                    name,
+                    total: 0,
+                    transactions: Vec::new(),
+                });
+                sources.len() - 1
+            }
+        };
+        sources[index].total += transaction.base_amount();
+        sources[index].transactions.push(transaction.clone());
+    }
+    sources.sort_by_key(|source| std::cmp::Reverse(source.total));
+    sources
+}
+
+#[derive(Template)]
+#[template(path = "expenses.html")]
+struct ExpensesTemplate<'a> {
+    month_label: String,
+    // Per-currency (expenses, incoming) totals
+    totals: BTreeMap<String, (i64, i64)>,
+    // Category name and total spent, largest first
+    summary: Vec<(String, i64)>,
+    income: Vec<IncomeSource>,
+    categories: Vec<BudgetCategory>,
+    // Every transaction counted above as a journal entry, newest first
+    journal: Vec<LedgerEntry>,
+    include_transfers: bool,
+    // Keeps the account filter when toggling transfers
+    filter_params: String,
+    config: &'a Config,
+}
+
+async fn get_expenses(
+    query: web::Query<AccountQuery>,
+    (up, account_cache): (web::Data<UpClient>, web::Data<AccountCache>),
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    (categories, rules): (web::Data<BudgetConfig>, web::Data<Rules>),
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
     };
+    // Get the current budget period
+    let (current_year, current_month) = current_period(config.fiscal_start_day);
+    let (since, until) = current_month_range(config.fiscal_start_day);
 
-    let client = Client::new();
-    let mut transactions = Vec::new();
-    let mut total_expenses = 0.0;
-    let mut total_incoming = 0.0;
-    let mut next_page_url = Some(format!(
-        "https://api.up.com.au/api/v1/transactions?filter[since]={}&filter[until]={}&filter[status]=SETTLED&page[size]=100",
-        start_date, end_date
-    ));
+    let mut month_transactions = fetch_range(sources, since, until, query_flag(&query.refresh))
+        .await
+        .map_err(upstream_error)?;
+    filter_by_account(&mut month_transactions, selected_account(&query.account_id));
+    exclude_held(&mut month_transactions, false);
+    let include_transfers = query_flag(&query.include_transfers);
+    exclude_transfers(&mut month_transactions, include_transfers);
+
+    let totals = position_totals(&month_transactions);
+    let (incoming, _) = split_income(&month_transactions);
+    let income = income_sources(&incoming);
+
+    let mut categorized = categorize(
+        &month_transactions,
+        &categories.resolve_allocations(current_year, current_month),
+        &rules,
+    );
+    // Account names only make the journal easier to read, so fall back to ids
+    // rather than failing the page
+    let accounts = account_infos(&up, &account_cache)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("failed to fetch account names for the journal: {}", e);
+            Vec::new()
+        });
+    let journal = ledger_entries(
+        &month_transactions,
+        &categorized,
+        &accounts,
+        config.display_tz,
+    );
 
-    // Loop to handle pagination
-    while let Some(url) = next_page_url {
-        let response = client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", api_key))
-            .send()
-            .await
-            .expect("Failed to send request");
-
-        if response.status().is_success() {
-            let json: Value = response.json().await.expect("Failed to parse response");
-            if let Some(data) = json["data"].as_array() {
-                for transaction in data {
-                    let description = transaction["attributes"]["description"]
-                        .as_str()
-                        .unwrap_or("Unknown");
-                    let amount = transaction["attributes"]["amount"]["value"]
-                        .as_str()
-                        .unwrap_or("0.00")
-                        .parse::<f64>()
-                        .unwrap_or(0.0);
-                    let date = transaction["attributes"]["createdAt"]
-                        .as_str()
-                        .unwrap_or("Unknown");
-
-                    // Track total expenses and incoming money
-                    if amount < 0.0 {
-                        total_expenses += amount.abs(); // Expenses are typically negative amounts
-                    } else {
-                        total_incoming += amount; // Positive amounts are incoming money
-                    }
-
-                    // Double-entry: Debit the expense (assume "Expenses" as a placeholder) and Credit the Spending account
-                    transactions.push(format!(
-                        "<li class=\"list-group-item\">{} - Debit: Expenses {:.2} AUD, Credit: Account {:.2} AUD</li>",
-                        date, amount.abs(), amount.abs()
-                    ));
-                }
+    // Only categories that actually saw spending are worth listing
+    categorized.retain(|category| category.spent_amount > 0);
+    categorized.sort_by_key(|category| std::cmp::Reverse(category.spent_amount));
 
-                // Handle pagination by setting next_page_url to the next link or None if there isn't one
-                next_page_url = json["links"]["next"].as_str().map(|s| s.to_string());
-            } else {
-                break; // No data, exit the loop
+    let summary = categorized
+        .iter()
+        .map(|category| (category.name.clone(), category.spent_amount))
+        .collect();
+
+    Ok(render_template(
+        &ExpensesTemplate {
+            month_label: format!("{}/{}", current_month, current_year),
+            totals,
+            summary,
+            income,
+            categories: categorized,
+            journal,
+            include_transfers,
+            filter_params: selected_account(&query.account_id)
+                .map(|id| format!("&account_id={}", encode_query_value(id)))
+                .unwrap_or_default(),
+            config: &config,
+        },
+        &config,
+    ))
+}
+
+// Resolves on Ctrl-C, or on SIGTERM from a container runtime or init system.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let ctrl_c = Box::pin(actix_web::rt::signal::ctrl_c());
+                let terminate = Box::pin(terminate.recv());
+                futures::future::select(ctrl_c, terminate).await;
+                return;
             }
-        } else {
-            break; // Stop on any error response
+            Err(e) => warn!("failed to listen for SIGTERM: {}", e),
         }
     }
 
-    let body = format!(
-    "<!DOCTYPE html>
-    <html lang=\"en\">
-    <head>
-        <meta charset=\"UTF-8\">
-        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-        <title>Expenses for Current Month</title>
-        <link href=\"https://stackpath.bootstrapcdn.com/bootstrap/4.5.2/css/bootstrap.min.css\" rel=\"stylesheet\">
-        <style>
-            .negative {{ color: red; }}
-        </style>
-    </head>
-    <body>
-        <nav class=\"navbar navbar-expand-lg navbar-light bg-light\">
-            <a class=\"navbar-brand\" href=\"\\\">My Bank App</a>
-        </nav>
-        <div class=\"container\">
-            <h1 class=\"my-4\">Expenses for {}/{} </h1>
-            <h3>Total Expenses: <span class=\"{}\">{:.2} AUD    Total Incoming Money: {:.2} AUD</span></h3>
-        <h3>Change in position: {:.2} AUD</h3>
-            <ul class=\"list-group\">{}</ul>
-        </div>
-    </body>
-    <footer class=\"footer mt-auto py-3 bg-light\">
-        <div class=\"container\">
-            <span class=\"text-muted\">Powered by My Bank App.</span>
-        </div>
-    </footer>
-    </html>",
-    current_month,
-    current_year,
-    if total_expenses > 0.0 { "" } else { "negative" }, // Apply "negative" class if expenses are negative
-    total_expenses*-1.0,
-    total_incoming,
-    total_incoming - total_expenses,
-    transactions.join("")
-);
-
-    actix_web::HttpResponse::Ok()
-        .content_type("text/html; charset=utf-8")
-        .body(body)
+    let _ = actix_web::rt::signal::ctrl_c().await;
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    dotenv().ok();
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let transaction_cache = web::Data::new(TransactionCache::new(
+        config.cache_ttl,
+        config.fiscal_start_day,
+    ));
+    let account_cache = web::Data::new(AccountCache::new(config.accounts_cache_ttl));
+
+    let database = match Database::open(&config.database_path, config.fiscal_start_day) {
+        Ok(database) => web::Data::new(database),
+        Err(e) => {
+            error!(
+                "failed to open database {}: {}",
+                config.database_path.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let up_client = match UpClient::new(
+        config.api_key.clone(),
+        config.retry_policy,
+        config.timeouts,
+        config.max_pages,
+        config.concurrency,
+    ) {
+        Ok(client) if config.demo_mode => {
+            warn!("DEMO_MODE is on: serving sample data, Up Bank will not be contacted");
+            web::Data::new(client.with_demo_data())
+        }
+        Ok(client) => web::Data::new(client),
+        Err(e) => {
+            error!("failed to build HTTP client: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if config.app_token.is_none() {
+        warn!("APP_TOKEN is not set; the dashboard is served without authentication");
+    }
+
+    let budget_categories =
+        match config::load_budget_config(&config.categories_path, &config.base_currency) {
+            Ok(categories) => web::Data::new(categories),
+            Err(e) => {
+                error!("invalid budget configuration: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+    let currency_rates =
+        match config::load_currency_rates(&config.rates_path, &config.base_currency) {
+            Ok(rates) => web::Data::new(rates),
+            Err(e) => {
+                error!("invalid currency rates: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+    let rules = match config::load_rules(&config.rules_path) {
+        Ok(rules) => web::Data::new(rules),
+        Err(e) => {
+            error!("invalid rules in {}: {}", config.rules_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let config = web::Data::new(config);
+
+    if env::args().skip(1).any(|arg| arg == "--report") {
+        let sources = Sources {
+            up: &up_client,
+            cache: &transaction_cache,
+            db: &database,
+            rates: &currency_rates,
+            config: &config,
+            rules: &rules,
+        };
+        let status = report::run(sources, &budget_categories).await;
+        std::process::exit(status);
+    }
+
+    if let Some(alerts) = config.alerts.clone() {
+        alerts::spawn_alerts(
+            alerts,
+            up_client.clone(),
+            transaction_cache.clone(),
+            database.clone(),
+            currency_rates.clone(),
+            config.clone(),
+            (budget_categories.clone(), rules.clone()),
+        );
+    }
+
+    if let Some(every) = config.snapshot_interval {
+        networth::spawn_snapshots(
+            every,
+            up_client.clone(),
+            account_cache.clone(),
+            database.clone(),
+            currency_rates.clone(),
+            config.clone(),
+        );
+    }
+
+    let bind_address = config.bind_address.clone();
+    let port = config.port;
+    let shutdown_timeout = config.shutdown_timeout_secs;
+    let workers = config.workers;
+    let keep_alive = match config.keep_alive {
+        Some(duration) => KeepAlive::Timeout(duration),
+        None => KeepAlive::Disabled,
+    };
+
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap(from_fn(auth::require_token))
+            .wrap(from_fn(timeout::enforce))
+            .wrap(from_fn(metrics::record))
+            // Encodes responses with gzip, brotli or zstd when the client
+            // accepts it, so long transaction lists and exports download faster
+            .wrap(Compress::default())
+            .wrap(Logger::default())
+            .app_data(config.clone())
+            .app_data(up_client.clone())
+            .app_data(transaction_cache.clone())
+            .app_data(account_cache.clone())
+            .app_data(database.clone())
+            .app_data(budget_categories.clone())
+            .app_data(rules.clone())
+            .app_data(currency_rates.clone())
             .route("/", web::get().to(landing_page))
+            .route("/healthz", web::get().to(api::healthz))
+            .route("/login", web::get().to(auth::login_page))
+            .route("/login", web::post().to(auth::login))
+            .route("/readyz", web::get().to(api::readyz))
+            .route("/metrics", web::get().to(metrics::metrics))
             .route("/allbalances", web::get().to(show_balances))
             .route("/balances", web::get().to(get_balances))
             .route("/expenses", web::get().to(get_expenses))
             .route("/accounts", web::get().to(list_accounts))
+            .route("/attachments/{id}", web::get().to(open_attachment))
+            .route("/webhook", web::post().to(webhook::receive))
+            .route("/sync", web::post().to(api::sync))
             .service(web::resource("/budget").route(web::get().to(budget_page)))
+            .route("/ytd", web::get().to(ytd::ytd_page))
+            .route("/merchants", web::get().to(merchants::merchants_page))
+            .route("/calendar", web::get().to(calendar::calendar_page))
+            .route("/weekly", web::get().to(weekly::weekly_page))
+            .route("/networth", web::get().to(networth::networth_page))
+            .route("/review", web::get().to(review::review_page))
+            .route("/review", web::post().to(review::suggest_rule))
+            .route("/rules", web::get().to(rules::rules_page))
+            .route(
+                "/subscriptions",
+                web::get().to(subscriptions::subscriptions_page),
+            )
+            .route(
+                "/export/transactions.csv",
+                web::get().to(export::transactions_csv),
+            )
+            .route(
+                "/export/budget.pdf",
+                web::get().to(export::budget_report_pdf),
+            )
+            .route(
+                "/export/transactions.ofx",
+                web::get().to(export::transactions_ofx),
+            )
+            .service(
+                web::scope("/api")
+                    .app_data(web::QueryConfig::default().error_handler(api::query_error))
+                    .route("/budget", web::get().to(api::budget))
+                    .route("/transactions", web::get().to(api::transactions))
+                    .route("/accounts", web::get().to(api::accounts))
+                    .route("/ping", web::get().to(api::ping))
+                    .route("/up-categories", web::get().to(api::up_categories))
+                    .route("/rules", web::get().to(api::rules)),
+            )
             .service(actix_files::Files::new("/static", "static").show_files_listing())
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    // Signals are handled below instead of by actix so shutdown can be logged
+    .workers(workers)
+    .keep_alive(keep_alive)
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .bind((bind_address.as_str(), port))?
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        info!(
+            "shutting down, draining connections for up to {}s",
+            shutdown_timeout
+        );
+        handle.stop(true).await;
+    });
+
+    server.await?;
+    // Every database write commits before its request finishes, so nothing
+    // is left to flush once connections have drained
+    info!("shutdown complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A settled AUD transaction, for tests across the crate to adjust as
+    // they need. `amount` is in cents.
+    pub(crate) fn transaction(id: &str, description: &str, amount: i64) -> Transaction {
+        Transaction {
+            id: id.to_string(),
+            date: "2024-03-05T10:00:00+11:00".to_string(),
+            settled_at: None,
+            description: description.to_string(),
+            amount,
+            currency: "AUD".to_string(),
+            status: "SETTLED".to_string(),
+            account_id: "account".to_string(),
+            round_up: None,
+            converted_amount: None,
+            tags: Vec::new(),
+            is_transfer: false,
+            up_category: None,
+            attachment_id: None,
+        }
+    }
+
+    fn category(name: &str, allocated: i64, spent: i64) -> BudgetCategory {
+        let mut category = BudgetCategory::new(name, Allocation::Fixed(allocated));
+        category.spent_amount = spent;
+        category
+    }
+
+    #[test]
+    fn budget_totals_of_no_categories_are_zero() {
+        assert_eq!(budget_totals(&[]), (0, 0, 0));
+    }
+
+    #[test]
+    fn budget_totals_sum_every_category() {
+        let categories = [
+            category("Groceries", 40000, 12050),
+            category("Transport", 10000, 3000),
+        ];
+        assert_eq!(budget_totals(&categories), (50000, 15050, 34950));
+    }
+
+    #[test]
+    fn budget_totals_go_negative_when_overspent() {
+        let categories = [
+            category("Groceries", 40000, 45000),
+            category("Eating Out", 10000, 15000),
+        ];
+        assert_eq!(budget_totals(&categories), (50000, 60000, -10000));
+    }
+
+    #[test]
+    fn normalize_words_collapses_punctuation_and_case() {
+        assert_eq!(normalize_words("Corner Bar & Grill"), "corner bar grill");
+        assert_eq!(
+            normalize_words("  WOOLWORTHS/1234--Sydney "),
+            "woolworths 1234 sydney"
+        );
+        assert_eq!(normalize_words("&&"), "");
+    }
+
+    #[test]
+    fn contains_words_matches_whole_words_only() {
+        let matches = |description: &str, keyword: &str| {
+            contains_words(&normalize_words(description), &normalize_words(keyword))
+        };
+        assert!(matches("Corner Bar", "bar"));
+        assert!(matches("Bar & Grill", "bar"));
+        assert!(matches("Corner Bar & Grill", "bar grill"));
+        assert!(!matches("Barber", "bar"));
+        assert!(!matches("Crowbar", "bar"));
+        // A later whole-word match counts even after a partial one
+        assert!(matches("Barber next to the Bar", "bar"));
+    }
+
+    #[test]
+    fn dedupe_by_id_drops_repeats_across_overlapping_pages() {
+        // A transaction arriving mid-fetch pushes "b" onto the second page too
+        let first_page = vec![
+            transaction("a", "Coles", -1000),
+            transaction("b", "Aldi", -500),
+        ];
+        let second_page = vec![
+            transaction("b", "Aldi", -500),
+            transaction("c", "IGA", -250),
+        ];
+        let mut transactions = [first_page, second_page].concat();
+        dedupe_by_id(&mut transactions);
+
+        let ids: Vec<&str> = transactions.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+    }
 }
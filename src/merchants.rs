@@ -0,0 +1,134 @@
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+use crate::cache::TransactionCache;
+use crate::config::{Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::export::selected_transactions;
+use crate::money::filters;
+use crate::up::UpClient;
+use crate::{
+    next_month, normalize_words, previous_month, render_template, BudgetQuery, Sources, Transaction,
+};
+
+// How many merchants the page lists.
+const MERCHANT_LIMIT: usize = 50;
+
+#[derive(Template)]
+#[template(path = "merchants.html")]
+struct MerchantsTemplate {
+    month_label: String,
+    prev_year: i32,
+    prev_month: u32,
+    next_year: i32,
+    next_month: u32,
+    merchants: Vec<(String, i64, usize)>,
+}
+
+// Groups descriptions that differ only in case, punctuation or store numbers,
+// so "WOOLWORTHS 1234" and "Woolworths" count as the same merchant.
+pub fn merchant_name(description: &str) -> String {
+    normalize_words(description)
+        .split(' ')
+        .filter(|word| !word.chars().all(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The `n` merchants with the most spending, as (merchant, total spent in
+// minor units, transaction count), largest first. Incoming money is ignored.
+pub fn top_merchants(transactions: &[Transaction], n: usize) -> Vec<(String, i64, usize)> {
+    let mut totals: HashMap<String, (i64, usize)> = HashMap::new();
+    for transaction in transactions {
+        let amount = transaction.base_amount();
+        if amount >= 0 {
+            continue;
+        }
+        let name = merchant_name(&transaction.description);
+        if name.is_empty() {
+            continue;
+        }
+        let (total, count) = totals.entry(name).or_default();
+        *total += amount.abs();
+        *count += 1;
+    }
+
+    let mut merchants: Vec<_> = totals
+        .into_iter()
+        .map(|(name, (total, count))| (name, total, count))
+        .collect();
+    merchants.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    merchants.truncate(n);
+    merchants
+}
+
+pub async fn merchants_page(
+    query: web::Query<BudgetQuery>,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, transactions) = selected_transactions(&query, sources).await?;
+
+    let (prev_year, prev_month) = previous_month(year, month);
+    let (next_year, next_month) = next_month(year, month);
+    Ok(render_template(
+        &MerchantsTemplate {
+            month_label: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|date| date.format("%B %Y").to_string())
+                .unwrap_or_else(|| format!("{}/{}", month, year)),
+            prev_year,
+            prev_month,
+            next_year,
+            next_month,
+            merchants: top_merchants(&transactions, MERCHANT_LIMIT),
+        },
+        &config,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::transaction;
+
+    #[test]
+    fn merchant_name_drops_case_punctuation_and_store_numbers() {
+        assert_eq!(merchant_name("WOOLWORTHS 1234"), "woolworths");
+        assert_eq!(merchant_name("Woolworths"), "woolworths");
+        assert_eq!(merchant_name("Woolworths/Metro #88"), "woolworths metro");
+        assert_eq!(merchant_name("1234"), "");
+    }
+
+    #[test]
+    fn top_merchants_merges_store_numbers_and_skips_income() {
+        let transactions = [
+            transaction("a", "WOOLWORTHS 1234", -5000),
+            transaction("b", "Woolworths", -2550),
+            transaction("c", "Coles 0042", -9000),
+            transaction("d", "Woolworths refund", 1000),
+            transaction("e", "1234", -100),
+        ];
+        assert_eq!(
+            top_merchants(&transactions, 10),
+            [
+                ("coles".to_string(), 9000, 1),
+                ("woolworths".to_string(), 7550, 2),
+            ]
+        );
+        assert_eq!(top_merchants(&transactions, 1).len(), 1);
+    }
+}
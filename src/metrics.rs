@@ -0,0 +1,140 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse};
+use log::error;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+// Counters and histograms exposed on `/metrics` for Prometheus to scrape.
+pub struct Metrics {
+    registry: Registry,
+    // Calls to Up Bank by outcome: "success", "client_error", "server_error",
+    // "rate_limited" or "failed" when no response came back
+    up_requests: IntCounterVec,
+    up_request_duration: HistogramVec,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    // Transaction cache lookups by result, "hit" or "miss"; the hit ratio is
+    // `hit / (hit + miss)`
+    cache_lookups: IntCounterVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let up_requests = IntCounterVec::new(
+            Opts::new("up_requests_total", "Requests sent to the Up Bank API"),
+            &["outcome"],
+        )
+        .unwrap();
+        let up_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "up_request_duration_seconds",
+                "Time taken by each Up Bank API request",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let http_requests = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "Requests served, by route and status class",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to serve each request",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("cache_lookups_total", "Transaction cache lookups"),
+            &["result"],
+        )
+        .unwrap();
+
+        for collector in [&up_requests, &http_requests, &cache_lookups] {
+            registry.register(Box::new(collector.clone())).unwrap();
+        }
+        for collector in [&up_request_duration, &http_request_duration] {
+            registry.register(Box::new(collector.clone())).unwrap();
+        }
+
+        Metrics {
+            registry,
+            up_requests,
+            up_request_duration,
+            http_requests,
+            http_request_duration,
+            cache_lookups,
+        }
+    }
+
+    pub fn record_up_request(&self, outcome: &str, elapsed: Duration) {
+        self.up_requests.with_label_values(&[outcome]).inc();
+        self.up_request_duration
+            .with_label_values(&[outcome])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.cache_lookups.with_label_values(&[result]).inc();
+    }
+}
+
+// Records the duration and status class ("2xx", "4xx", ...) of every request.
+// Requests are labelled by their route pattern rather than the raw path, so
+// ids in the URL don't create a series each.
+pub async fn record(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let result = next.call(req).await;
+
+    // Errors raised by middleware, such as timeouts, carry their own status
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    let status_class = format!("{}xx", status.as_u16() / 100);
+    METRICS
+        .http_requests
+        .with_label_values(&[method.as_str(), route.as_str(), status_class.as_str()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[method.as_str(), route.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+
+    result
+}
+
+// `/metrics`: everything recorded so far in Prometheus' text format.
+pub async fn metrics() -> HttpResponse {
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        error!("failed to encode metrics: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(ContentType(encoder.format_type().parse().unwrap()))
+        .body(buffer)
+}
@@ -0,0 +1,139 @@
+// The symbol and number of decimal places for common currencies. Dollars are
+// told apart by prefix, except for the base currency's plain "$".
+fn currency_style(code: &str) -> Option<(&'static str, usize)> {
+    let style = match code {
+        "AUD" => ("A$", 2),
+        "USD" => ("US$", 2),
+        "NZD" => ("NZ$", 2),
+        "CAD" => ("CA$", 2),
+        "SGD" => ("S$", 2),
+        "HKD" => ("HK$", 2),
+        "EUR" => ("€", 2),
+        "GBP" => ("£", 2),
+        "CHF" => ("CHF\u{a0}", 2),
+        "JPY" => ("¥", 0),
+        "CNY" => ("CN¥", 2),
+        "KRW" => ("₩", 0),
+        "INR" => ("₹", 2),
+        "IDR" => ("Rp", 0),
+        "VND" => ("₫", 0),
+        "THB" => ("฿", 2),
+        "PHP" => ("₱", 2),
+        _ => return None,
+    };
+    Some(style)
+}
+
+// How many decimal places a currency's amounts have, which is also how many
+// powers of ten of its minor unit make one whole unit: 2 for cents, 0 for yen.
+pub fn decimal_places(currency: &str) -> u32 {
+    currency_style(&currency.trim().to_uppercase()).map_or(2, |(_, decimals)| decimals as u32)
+}
+
+// An amount in the currency's minor units, like Up Bank's
+// `valueInBaseUnits`, as whole units: 1250 cents is 12.5 dollars.
+pub fn from_minor_units(units: i64, currency: &str) -> f64 {
+    units as f64 / 10f64.powi(decimal_places(currency) as i32)
+}
+
+// The reverse of `from_minor_units`, rounding to the nearest minor unit, for
+// amounts that start out in whole units such as configured allocations.
+pub fn to_minor_units(amount: f64, currency: &str) -> i64 {
+    (amount * 10f64.powi(decimal_places(currency) as i32)).round() as i64
+}
+
+// An amount as whole units of `currency`, whether it is already in whole
+// units or in minor units, and however askama hands it over: by value or
+// behind references.
+pub trait Amount {
+    fn value(&self, currency: &str) -> f64;
+}
+
+// Already in whole units, such as account balances
+impl Amount for f64 {
+    fn value(&self, _: &str) -> f64 {
+        *self
+    }
+}
+
+// Minor units, such as transaction amounts and totals
+impl Amount for i64 {
+    fn value(&self, currency: &str) -> f64 {
+        from_minor_units(*self, currency)
+    }
+}
+
+impl<T: Amount + ?Sized> Amount for &T {
+    fn value(&self, currency: &str) -> f64 {
+        (**self).value(currency)
+    }
+}
+
+// Writes `amount` the way its currency usually is, e.g. "$12.50" in the base
+// currency `base`, "US$12.50", "€12.50" or "¥1250". Currencies without a
+// known symbol get their code after the amount, "12.50 XYZ". Negative amounts
+// lead with a minus sign, "−$12.50".
+pub fn format_money(amount: f64, currency: &str, base: &str) -> String {
+    let code = currency.trim().to_uppercase();
+    let sign = if amount <= -0.005 { "\u{2212}" } else { "" };
+    match currency_style(&code) {
+        Some((symbol, decimals)) => {
+            let symbol = if code == base && symbol.ends_with('$') {
+                "$"
+            } else {
+                symbol
+            };
+            format!("{}{}{:.*}", sign, symbol, decimals, amount.abs())
+        }
+        None => format!("{}{:.2} {}", sign, amount.abs(), code),
+    }
+}
+
+// `format_money` in the base currency `base`.
+pub fn format_base(amount: impl Amount, base: &str) -> String {
+    format_money(amount.value(base), base, base)
+}
+
+// `format_base` without the cents, for chart axes and other tight spots.
+pub fn format_base_whole(amount: impl Amount, base: &str) -> String {
+    let amount = amount.value(base);
+    let sign = if amount <= -0.5 { "\u{2212}" } else { "" };
+    match currency_style(base) {
+        Some((symbol, _)) if symbol.ends_with('$') => format!("{}${:.0}", sign, amount.abs()),
+        Some((symbol, _)) => format!("{}{}{:.0}", sign, symbol, amount.abs()),
+        None => format!("{}{:.0} {}", sign, amount.abs(), base),
+    }
+}
+
+// Template filters: `{{ amount|money }}` for base currency amounts,
+// `{{ amount|money_whole }}` for the same without cents and
+// `{{ amount|money_in(currency) }}` for anything else. The base currency
+// comes from the runtime values templates are rendered with. Integer amounts
+// are minor units, and only become whole units here.
+pub mod filters {
+    use super::{format_base, format_base_whole, format_money, Amount};
+
+    fn base_currency(values: &dyn askama::Values) -> askama::Result<&str> {
+        askama::get_value::<String>(values, "base_currency").map(String::as_str)
+    }
+
+    pub fn money(amount: impl Amount, values: &dyn askama::Values) -> askama::Result<String> {
+        Ok(format_base(amount, base_currency(values)?))
+    }
+
+    pub fn money_whole(amount: impl Amount, values: &dyn askama::Values) -> askama::Result<String> {
+        Ok(format_base_whole(amount, base_currency(values)?))
+    }
+
+    pub fn money_in(
+        amount: impl Amount,
+        values: &dyn askama::Values,
+        currency: &str,
+    ) -> askama::Result<String> {
+        Ok(format_money(
+            amount.value(currency),
+            currency,
+            base_currency(values)?,
+        ))
+    }
+}
@@ -0,0 +1,142 @@
+use actix_web::http::StatusCode;
+use actix_web::rt::time::interval;
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use chrono::{NaiveDate, Utc};
+use log::{error, info, warn};
+use std::time::Duration;
+
+use crate::cache::AccountCache;
+use crate::chart::networth_chart;
+use crate::config::{Config, CurrencyRates};
+use crate::db::Database;
+use crate::money::{filters, to_minor_units};
+use crate::up::{UpClient, UpError};
+use crate::{error_page, live_accounts, render_template};
+
+// The combined balance of every account at the end of a day, or at the last
+// snapshot taken that day. Up Bank only reports current balances, so these
+// are the only record of how they changed over time.
+pub struct BalanceSnapshot {
+    // "YYYY-MM-DD" in the display timezone
+    pub date: String,
+    pub total: f64,
+    pub currency: String,
+}
+
+// Sums the current balances into the base currency and records them as
+// today's snapshot, replacing any taken earlier today. Accounts in a currency
+// without a rate are left out rather than counted unconverted.
+async fn take_snapshot(
+    up: &UpClient,
+    account_cache: &AccountCache,
+    db: &Database,
+    rates: &CurrencyRates,
+    config: &Config,
+) -> Result<BalanceSnapshot, UpError> {
+    let accounts = live_accounts(up, account_cache).await?;
+    let mut total = 0.0;
+    for account in &accounts {
+        let balance = &account.attributes.balance;
+        match rates.convert(balance.amount(), &balance.currency_code) {
+            Some(amount) => total += amount,
+            None => warn!(
+                "net worth: no exchange rate for {}, leaving out {}",
+                balance.currency_code, account.attributes.display_name
+            ),
+        }
+    }
+
+    let snapshot = BalanceSnapshot {
+        date: Utc::now()
+            .with_timezone(&config.display_tz)
+            .format("%Y-%m-%d")
+            .to_string(),
+        total,
+        currency: config.base_currency.clone(),
+    };
+    if let Err(e) = db.save_snapshot(&snapshot) {
+        warn!("net worth: failed to store snapshot: {}", e);
+    }
+    Ok(snapshot)
+}
+
+// Snapshots the balances every `every`, starting straight away, for as long
+// as the server runs.
+pub fn spawn_snapshots(
+    every: Duration,
+    up: web::Data<UpClient>,
+    account_cache: web::Data<AccountCache>,
+    db: web::Data<Database>,
+    rates: web::Data<CurrencyRates>,
+    config: web::Data<Config>,
+) {
+    info!(
+        "net worth snapshots enabled every {} hours",
+        every.as_secs() / 3600
+    );
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(every);
+        loop {
+            ticks.tick().await;
+            match take_snapshot(&up, &account_cache, &db, &rates, &config).await {
+                Ok(snapshot) => info!(
+                    "net worth snapshot for {}: {:.2} {}",
+                    snapshot.date, snapshot.total, snapshot.currency
+                ),
+                Err(e) => warn!("net worth: failed to fetch balances: {}", e),
+            }
+        }
+    });
+}
+
+#[derive(Template)]
+#[template(path = "networth.html")]
+struct NetWorthTemplate<'a> {
+    chart: String,
+    // Most recent first
+    snapshots: Vec<BalanceSnapshot>,
+    // Latest total less the earliest one, in minor units
+    change: i64,
+    config: &'a Config,
+}
+
+impl NetWorthTemplate<'_> {
+    fn date_label(&self, date: &str) -> String {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|date| date.format("%-d %b %Y").to_string())
+            .unwrap_or_else(|_| date.to_string())
+    }
+}
+
+// `/networth`: the recorded snapshots of total balance, charted over time.
+pub async fn networth_page(db: web::Data<Database>, config: web::Data<Config>) -> HttpResponse {
+    let snapshots = match db.load_snapshots(&config.base_currency) {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            error!("failed to load balance snapshots: {}", e);
+            return error_page(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Net Worth Unavailable",
+                "The recorded balances couldn't be read from the database.",
+            );
+        }
+    };
+    let change = match (snapshots.first(), snapshots.last()) {
+        (Some(first), Some(last)) => {
+            to_minor_units(last.total - first.total, &config.base_currency)
+        }
+        _ => 0,
+    };
+    let chart = networth_chart(&snapshots, &config.base_currency);
+
+    render_template(
+        &NetWorthTemplate {
+            chart,
+            snapshots: snapshots.into_iter().rev().collect(),
+            change,
+            config: &config,
+        },
+        &config,
+    )
+}
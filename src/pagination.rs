@@ -0,0 +1,65 @@
+// Transactions shown per page unless `?per_page=` says otherwise.
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+
+// Which slice of a long transaction list to show, from `?page=` (counting
+// from 1) and `?per_page=`. Totals are worked out before paging, so only the
+// listed rows change from page to page.
+#[derive(Clone, Copy)]
+pub struct Pagination {
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl Pagination {
+    // Out of range values are clamped rather than rejected, so a stale link
+    // still shows something.
+    pub fn new(page: Option<usize>, per_page: Option<usize>) -> Self {
+        Pagination {
+            page: page.unwrap_or(1).max(1),
+            per_page: per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE),
+        }
+    }
+
+    // Saturates rather than overflowing on an absurd `?page=`, which just
+    // lands past the last page.
+    fn offset(&self) -> usize {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+
+    // The current page's share of `items`; empty past the last page.
+    pub fn items<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let start = self.offset().min(items.len());
+        let end = (start + self.per_page).min(items.len());
+        &items[start..end]
+    }
+
+    pub fn page_count(&self, total: usize) -> usize {
+        total.div_ceil(self.per_page).max(1)
+    }
+
+    // Query parameters for another page, like "&page=2", keeping a
+    // non-default page size.
+    pub fn params(&self, page: usize) -> String {
+        let mut params = format!("&page={}", page);
+        if self.per_page != DEFAULT_PER_PAGE {
+            params.push_str(&format!("&per_page={}", self.per_page));
+        }
+        params
+    }
+
+    // "Showing 51–100 of 230", or empty on the first page when everything
+    // fits on it.
+    pub fn range_label(&self, total: usize) -> String {
+        if self.page == 1 && total <= self.per_page {
+            return String::new();
+        }
+        let start = self.offset().min(total);
+        let end = (start + self.per_page).min(total);
+        if start == end {
+            format!("None of {} on this page", total)
+        } else {
+            format!("Showing {}\u{2013}{} of {}", start + 1, end, total)
+        }
+    }
+}
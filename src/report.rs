@@ -0,0 +1,92 @@
+use chrono::NaiveDate;
+use log::error;
+
+use crate::config::BudgetConfig;
+use crate::money::from_minor_units;
+use crate::{
+    apply_income_allocations, apply_prior_rollover, budget_totals, categorize_transactions,
+    current_month_range, current_period, exclude_held, fetch_range, overspent_categories, Sources,
+};
+
+// Exit status when any category has spent more than its allocation, so
+// scripts can alert on it.
+const EXIT_OVER_BUDGET: i32 = 1;
+// Exit status when the month's transactions couldn't be fetched.
+const EXIT_FETCH_FAILED: i32 = 2;
+
+// `--report`: prints the current month's budget as a table on stdout instead
+// of starting the server. Returns the process exit status.
+pub async fn run(sources: Sources<'_>, categories: &BudgetConfig) -> i32 {
+    let start_day = sources.config.fiscal_start_day;
+    let (year, month) = current_period(start_day);
+    let (since, until) = current_month_range(start_day);
+
+    let mut transactions = match fetch_range(sources, since, until, false).await {
+        Ok(transactions) => transactions,
+        Err(e) => {
+            error!("failed to fetch transactions: {}", e);
+            return EXIT_FETCH_FAILED;
+        }
+    };
+    exclude_held(&mut transactions, false);
+    let budget_categories = categories.resolve_allocations(year, month);
+    let mut categorized = categorize_transactions(transactions, budget_categories, sources.rules);
+    apply_income_allocations(&mut categorized);
+    apply_prior_rollover(
+        &mut categorized,
+        sources.db,
+        categories.rollover(),
+        year,
+        month,
+    );
+
+    let month_label = NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|date| date.format("%B %Y").to_string())
+        .unwrap_or_default();
+    let name_width = categorized
+        .iter()
+        .map(|category| category.name.len())
+        .chain(["Category".len(), "Total".len()])
+        .max()
+        .unwrap_or_default();
+
+    let base = sources.config.base_currency.as_str();
+    let units = |amount| from_minor_units(amount, base);
+
+    println!("Budget for {}", month_label);
+    println!();
+    println!(
+        "{:<name_width$}  {:>12}  {:>12}  {:>12}",
+        "Category", "Allocated", "Spent", "Remaining"
+    );
+    println!("{}", "-".repeat(name_width + 42));
+    for category in &categorized {
+        println!(
+            "{:<name_width$}  {:>12.2}  {:>12.2}  {:>12.2}{}",
+            category.name,
+            units(category.allocated_amount),
+            units(category.spent_amount),
+            units(category.remaining_amount()),
+            if category.spent_amount > category.allocated_amount {
+                "  over budget"
+            } else {
+                ""
+            }
+        );
+    }
+    let (total_allocated, total_spent, total_remaining) = budget_totals(&categorized);
+    println!("{}", "-".repeat(name_width + 42));
+    println!(
+        "{:<name_width$}  {:>12.2}  {:>12.2}  {:>12.2}",
+        "Total",
+        units(total_allocated),
+        units(total_spent),
+        units(total_remaining)
+    );
+
+    if overspent_categories(&categorized).is_empty() {
+        0
+    } else {
+        EXIT_OVER_BUDGET
+    }
+}
@@ -0,0 +1,190 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::cache::TransactionCache;
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::export::selected_transactions;
+use crate::merchants::merchant_name;
+use crate::up::UpClient;
+use crate::{
+    categorize_transactions, normalize_words, render_template, BudgetQuery, Sources, Transaction,
+};
+
+#[derive(Deserialize)]
+pub struct RuleForm {
+    year: i32,
+    month: u32,
+    keyword: String,
+    category: String,
+}
+
+#[derive(Template)]
+#[template(path = "review.html")]
+struct ReviewTemplate<'a> {
+    month_label: String,
+    year: i32,
+    month: u32,
+    fallback_name: String,
+    // Each uncategorized transaction with the keyword suggested for it
+    transactions: Vec<(Transaction, String)>,
+    category_names: Vec<String>,
+    // The TOML to add to the rules file, after a rule was submitted
+    suggested_rule: Option<String>,
+    error: Option<String>,
+    config: &'a Config,
+}
+
+// Rules are loaded once at startup, so rather than rewriting the rules file
+// underneath the running server this hands back the entry to add to it.
+fn rule_toml(keyword: &str, category: &str) -> String {
+    format!(
+        "[[rules]]\ncategory = {}\nkeywords = [{}]\n",
+        toml::Value::String(category.to_string()),
+        toml::Value::String(keyword.to_string())
+    )
+}
+
+fn render_review(
+    (year, month): (i32, u32),
+    transactions: Vec<Transaction>,
+    (categories, rules): (&BudgetConfig, &Rules),
+    config: &Config,
+    form: Option<&RuleForm>,
+) -> HttpResponse {
+    let categorized = categorize_transactions(
+        transactions,
+        categories.resolve_allocations(year, month),
+        rules,
+    );
+
+    let category_names: Vec<String> = categorized
+        .iter()
+        .filter(|category| !category.is_fallback)
+        .map(|category| category.name.clone())
+        .collect();
+    let fallback = categorized
+        .into_iter()
+        .find(|category| category.is_fallback);
+    let fallback_name = fallback
+        .as_ref()
+        .map_or_else(|| "Other".to_string(), |category| category.name.clone());
+    let transactions = fallback
+        .map(|category| category.transactions)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|transaction| {
+            let keyword = merchant_name(&transaction.description);
+            (transaction, keyword)
+        })
+        .collect();
+
+    let (suggested_rule, error) = match form {
+        None => (None, None),
+        Some(form) => {
+            let keyword = normalize_words(&form.keyword);
+            if keyword.is_empty() {
+                (None, Some("Enter a keyword to match.".to_string()))
+            } else if !category_names.contains(&form.category) {
+                (
+                    None,
+                    Some(format!("\"{}\" is not a budget category.", form.category)),
+                )
+            } else {
+                (Some(rule_toml(&keyword, &form.category)), None)
+            }
+        }
+    };
+    let status = if error.is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+
+    let mut response = render_template(
+        &ReviewTemplate {
+            month_label: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|date| date.format("%B %Y").to_string())
+                .unwrap_or_else(|| format!("{}/{}", month, year)),
+            year,
+            month,
+            fallback_name,
+            transactions,
+            category_names,
+            suggested_rule,
+            error,
+            config,
+        },
+        config,
+    );
+    if response.status().is_success() {
+        *response.status_mut() = status;
+    }
+    response
+}
+
+// Lists the month's uncategorized transactions so rules can be written for
+// them.
+pub async fn review_page(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+
+    let (year, month, transactions) = selected_transactions(&query, sources).await?;
+    Ok(render_review(
+        (year, month),
+        transactions,
+        (&categories, &rules),
+        &config,
+        None,
+    ))
+}
+
+pub async fn suggest_rule(
+    form: web::Form<RuleForm>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+
+    let query = BudgetQuery {
+        year: Some(form.year),
+        month: Some(form.month),
+        ..BudgetQuery::default()
+    };
+    let (year, month, transactions) = selected_transactions(&query, sources).await?;
+    Ok(render_review(
+        (year, month),
+        transactions,
+        (&categories, &rules),
+        &config,
+        Some(&form),
+    ))
+}
@@ -0,0 +1,250 @@
+use actix_web::{web, HttpResponse};
+use askama::Template;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::{BudgetConfig, Config, Rules};
+use crate::{
+    match_category, normalize_words, render_template, BudgetCategory, CategoryMatch, CategoryRule,
+    SplitRule, Transaction,
+};
+
+#[derive(Deserialize)]
+pub struct RulesQuery {
+    description: Option<String>,
+}
+
+// One rule as written in the rules file.
+#[derive(Serialize)]
+struct RuleSummary {
+    // Counting from 1 in file order; the first matching rule wins
+    position: usize,
+    keywords: Vec<String>,
+    regex: bool,
+}
+
+// A category and the rules sending transactions to it.
+#[derive(Serialize)]
+struct CategoryRules {
+    name: String,
+    is_fallback: bool,
+    // False when only rules name it; their matches land in the fallback
+    in_budget: bool,
+    rules: Vec<RuleSummary>,
+}
+
+// Where a description would be categorized, and why.
+#[derive(Serialize)]
+struct Explanation {
+    description: String,
+    // Dropped from every view by `[ignore]` before it gets categorized
+    ignored: bool,
+    // Where it lands, more than one for a split, like "Groceries (70%)"
+    categories: Vec<String>,
+    reason: String,
+    // Position of the matching rule and the keyword or regex that matched
+    rule: Option<usize>,
+    keyword: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RulesOverview<'a> {
+    categories: Vec<CategoryRules>,
+    splits: &'a [SplitRule],
+    // Up Bank category id to budget category
+    up_categories: BTreeMap<String, String>,
+    explanation: Option<Explanation>,
+}
+
+// A transaction with nothing but a description, as if it had just come in.
+fn bare_transaction(description: &str) -> Transaction {
+    Transaction {
+        id: String::new(),
+        date: String::new(),
+        settled_at: None,
+        description: description.to_string(),
+        amount: 0,
+        currency: String::new(),
+        status: "SETTLED".to_string(),
+        account_id: String::new(),
+        round_up: None,
+        converted_amount: None,
+        tags: Vec::new(),
+        is_transfer: false,
+        up_category: None,
+        attachment_id: None,
+    }
+}
+
+// Runs `description` through the same matching as `categorize`, naming the
+// category it ends up in as well as the one matched, since a rule for a
+// category missing from the budget lands in the fallback.
+fn explain(description: &str, budget: &[BudgetCategory], rules: &Rules) -> Explanation {
+    let fallback = budget
+        .iter()
+        .find(|category| category.is_fallback)
+        .map_or("Other", |category| category.name.as_str());
+    let landing = |name: &str| {
+        if budget.iter().any(|category| category.name == name) {
+            name.to_string()
+        } else {
+            format!("{} (not in the budget, so {})", name, fallback)
+        }
+    };
+
+    let transaction = bare_transaction(description);
+    let matched = match_category(&transaction, &normalize_words(description), budget, rules);
+    let (categories, reason, rule, keyword) = match &matched {
+        CategoryMatch::Split(split) => (
+            split
+                .shares
+                .iter()
+                .map(|(name, percent)| format!("{} ({}%)", landing(name), percent))
+                .collect(),
+            "Shared out by a split rule".to_string(),
+            None,
+            split.description.clone(),
+        ),
+        CategoryMatch::Tag(category) => (
+            vec![landing(category)],
+            format!("Tagged \"{}\"", category),
+            None,
+            None,
+        ),
+        CategoryMatch::UpCategory { id, category } => (
+            vec![landing(category)],
+            format!("Up Bank category \"{}\" is mapped to it", id),
+            None,
+            None,
+        ),
+        CategoryMatch::Rule(matched_rule, keyword) => {
+            let position = rules
+                .categories
+                .iter()
+                .position(|rule| std::ptr::eq(rule, *matched_rule))
+                .map(|index| index + 1);
+            let kind = if matched_rule.regex {
+                "regex"
+            } else {
+                "keyword"
+            };
+            (
+                vec![landing(&matched_rule.category)],
+                format!(
+                    "Rule {} matched the {} \"{}\"",
+                    position.unwrap_or_default(),
+                    kind,
+                    keyword
+                ),
+                position,
+                Some(keyword.to_string()),
+            )
+        }
+        CategoryMatch::Unmatched => (
+            vec![fallback.to_string()],
+            "No rule matched".to_string(),
+            None,
+            None,
+        ),
+    };
+
+    Explanation {
+        description: description.to_string(),
+        ignored: rules.ignore.ignores(description),
+        categories,
+        reason,
+        rule,
+        keyword,
+    }
+}
+
+// Every category with its rules in the default budget, and where
+// `?description=` would be categorized when given.
+pub fn rules_overview<'a>(
+    query: &RulesQuery,
+    categories: &BudgetConfig,
+    rules: &'a Rules,
+) -> RulesOverview<'a> {
+    let budget = categories.categories();
+    let summary = |(index, rule): (usize, &CategoryRule)| RuleSummary {
+        position: index + 1,
+        keywords: rule.keywords.clone(),
+        regex: rule.regex,
+    };
+
+    let mut listed: Vec<CategoryRules> = budget
+        .iter()
+        .map(|category| CategoryRules {
+            name: category.name.clone(),
+            is_fallback: category.is_fallback,
+            in_budget: true,
+            rules: rules
+                .categories
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.category == category.name)
+                .map(summary)
+                .collect(),
+        })
+        .collect();
+    // Rules for categories the budget doesn't have, after the real ones
+    for (index, rule) in rules.categories.iter().enumerate() {
+        if budget.iter().any(|category| category.name == rule.category) {
+            continue;
+        }
+        match listed
+            .iter_mut()
+            .find(|listed| listed.name == rule.category)
+        {
+            Some(listed) => listed.rules.push(summary((index, rule))),
+            None => listed.push(CategoryRules {
+                name: rule.category.clone(),
+                is_fallback: false,
+                in_budget: false,
+                rules: vec![summary((index, rule))],
+            }),
+        }
+    }
+
+    let explanation = query
+        .description
+        .as_deref()
+        .map(str::trim)
+        .filter(|description| !description.is_empty())
+        .map(|description| explain(description, budget, rules));
+
+    RulesOverview {
+        categories: listed,
+        splits: &rules.splits,
+        up_categories: rules
+            .up_categories
+            .iter()
+            .map(|(id, category)| (id.clone(), category.clone()))
+            .collect(),
+        explanation,
+    }
+}
+
+#[derive(Template)]
+#[template(path = "rules.html")]
+struct RulesTemplate<'a> {
+    overview: RulesOverview<'a>,
+    description: String,
+}
+
+// `/rules`: how transactions get categorized, for working out why one landed
+// where it did. `/api/rules` has the same as JSON.
+pub async fn rules_page(
+    query: web::Query<RulesQuery>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+    config: web::Data<Config>,
+) -> HttpResponse {
+    render_template(
+        &RulesTemplate {
+            overview: rules_overview(&query, &categories, &rules),
+            description: query.description.clone().unwrap_or_default(),
+        },
+        &config,
+    )
+}
@@ -0,0 +1,187 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::cache::TransactionCache;
+use crate::config::{Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::merchants::merchant_name;
+use crate::money::filters;
+use crate::up::UpClient;
+use crate::{
+    error_page, exclude_held, fetch_range, query_flag, render_template, upstream_error, Sources,
+    Transaction,
+};
+
+// Average days between monthly charges.
+const MONTH_DAYS: f64 = 30.44;
+// Fewest charges that count as a pattern rather than coincidence.
+const MIN_OCCURRENCES: usize = 3;
+
+#[derive(Deserialize)]
+pub struct SubscriptionsQuery {
+    months: Option<u32>,
+    amount_tolerance: Option<f64>,
+    interval_tolerance: Option<i64>,
+    refresh: Option<String>,
+}
+
+// How far charges may drift and still be treated as the same subscription.
+pub struct Tolerance {
+    // Fraction each charge may differ from the typical amount, e.g. 0.1
+    pub amount: f64,
+    // Days each gap between charges may differ from a month
+    pub interval_days: i64,
+}
+
+pub struct Subscription {
+    merchant: String,
+    // The median charge, in minor units of the base currency, which ignores
+    // a one-off price change better than the mean
+    amount: i64,
+    occurrences: usize,
+    last_charged: NaiveDate,
+    next_expected: NaiveDate,
+}
+
+fn local_date(transaction: &Transaction, tz: Tz) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(&transaction.date)
+        .ok()
+        .map(|date| date.with_timezone(&tz).date_naive())
+}
+
+// Finds merchants charged roughly monthly for a similar amount. Each gap
+// between consecutive charges must be within `interval_days` of a month and
+// each charge within `amount` of the median. Charges are dated in `tz`.
+pub fn detect_subscriptions(
+    transactions: &[Transaction],
+    tolerance: &Tolerance,
+    tz: Tz,
+) -> Vec<Subscription> {
+    let mut charges: HashMap<String, Vec<(NaiveDate, i64)>> = HashMap::new();
+    for transaction in transactions {
+        let amount = transaction.base_amount();
+        if amount >= 0 {
+            continue;
+        }
+        let (Some(date), name) = (
+            local_date(transaction, tz),
+            merchant_name(&transaction.description),
+        ) else {
+            continue;
+        };
+        if !name.is_empty() {
+            charges.entry(name).or_default().push((date, amount.abs()));
+        }
+    }
+
+    let mut subscriptions: Vec<_> = charges
+        .into_iter()
+        .filter_map(|(merchant, mut charges)| {
+            if charges.len() < MIN_OCCURRENCES {
+                return None;
+            }
+            charges.sort_by_key(|(date, _)| *date);
+
+            let monthly = charges.windows(2).all(|pair| {
+                let gap = (pair[1].0 - pair[0].0).num_days();
+                (gap as f64 - MONTH_DAYS).abs() <= tolerance.interval_days as f64
+            });
+            if !monthly {
+                return None;
+            }
+
+            let mut amounts: Vec<i64> = charges.iter().map(|(_, amount)| *amount).collect();
+            amounts.sort();
+            let median = amounts[amounts.len() / 2];
+            let similar = amounts
+                .iter()
+                .all(|amount| (amount - median).abs() as f64 <= median as f64 * tolerance.amount);
+            if !similar {
+                return None;
+            }
+
+            let last_charged = charges.last()?.0;
+            Some(Subscription {
+                merchant,
+                amount: median,
+                occurrences: charges.len(),
+                last_charged,
+                next_expected: last_charged + chrono::Duration::days(MONTH_DAYS.round() as i64),
+            })
+        })
+        .collect();
+    subscriptions.sort_by(|a, b| {
+        b.amount
+            .cmp(&a.amount)
+            .then_with(|| a.merchant.cmp(&b.merchant))
+    });
+    subscriptions
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions.html")]
+struct SubscriptionsTemplate {
+    months: u32,
+    subscriptions: Vec<Subscription>,
+    monthly_total: i64,
+}
+
+// Likely subscriptions seen over the last few months. Each month is fetched
+// through the cache and database, so only the current one usually reaches
+// Up Bank.
+pub async fn subscriptions_page(
+    query: web::Query<SubscriptionsQuery>,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let months = query.months.unwrap_or(6);
+    let tolerance = Tolerance {
+        amount: query.amount_tolerance.unwrap_or(0.1),
+        interval_days: query.interval_tolerance.unwrap_or(5),
+    };
+    if !(2..=24).contains(&months)
+        || !(0.0..=1.0).contains(&tolerance.amount)
+        || !(0..=15).contains(&tolerance.interval_days)
+    {
+        return Ok(error_page(
+            StatusCode::BAD_REQUEST,
+            "Invalid Search",
+            "Months must be between 2 and 24, amount_tolerance between 0 and 1, \
+             and interval_tolerance between 0 and 15 days.",
+        ));
+    }
+
+    let now = Utc::now();
+    let since = now - chrono::Duration::days((f64::from(months) * MONTH_DAYS) as i64);
+    let mut transactions = fetch_range(sources, since, now, query_flag(&query.refresh))
+        .await
+        .map_err(upstream_error)?;
+    exclude_held(&mut transactions, false);
+
+    let subscriptions = detect_subscriptions(&transactions, &tolerance, config.display_tz);
+    Ok(render_template(
+        &SubscriptionsTemplate {
+            months,
+            monthly_total: subscriptions.iter().map(|s| s.amount).sum(),
+            subscriptions,
+        },
+        &config,
+    ))
+}
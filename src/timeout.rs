@@ -0,0 +1,52 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::rt::time::timeout;
+use actix_web::{web, Error};
+use log::warn;
+
+use crate::api::ApiError;
+use crate::config::Config;
+use crate::error_page;
+
+// Answers 504 once a request has run longer than `REQUEST_TIMEOUT_SECS`, however
+// many Up Bank calls it was still waiting on. The handler is dropped at that
+// point, so anything it had fetched so far is discarded.
+pub async fn enforce(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let limit = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.request_timeout);
+    let Some(limit) = limit else {
+        return next.call(req).await;
+    };
+
+    // The request itself moves into the handler, and actix doesn't allow
+    // holding on to a clone of it, so the timeout is reported as an error
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    match timeout(limit, next.call(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("{} {} timed out after {}s", method, path, limit.as_secs());
+            if path.starts_with("/api/") {
+                return Err(ApiError::Timeout(format!(
+                    "request took longer than {} seconds",
+                    limit.as_secs()
+                ))
+                .into());
+            }
+            let response = error_page(
+                StatusCode::GATEWAY_TIMEOUT,
+                "Request Timed Out",
+                "Loading this page took too long, most likely because Up Bank is slow \
+                 right now. Please try again in a moment.",
+            );
+            Err(InternalError::from_response("request timed out", response).into())
+        }
+    }
+}
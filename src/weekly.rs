@@ -0,0 +1,162 @@
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::{DateTime, Datelike, IsoWeek, NaiveDate};
+use chrono_tz::Tz;
+
+use crate::cache::TransactionCache;
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::export::selected_transactions;
+use crate::money::filters;
+use crate::up::UpClient;
+use crate::{
+    categorize, exclude_transfers, next_month, period_start, previous_month, render_template,
+    BudgetQuery, Sources, Transaction,
+};
+
+// One ISO week of the budget period, cut down to the days inside the period
+// when it straddles the start or end.
+struct WeekRow {
+    week: IsoWeek,
+    first_day: NaiveDate,
+    last_day: NaiveDate,
+    // Spending in the same order as the template's category columns, in
+    // minor units of the base currency
+    spent: Vec<i64>,
+    total: i64,
+}
+
+impl WeekRow {
+    // "Week 10, 1–3 Mar"
+    fn label(&self) -> String {
+        let first = if self.first_day.month() == self.last_day.month() {
+            self.first_day.format("%-d").to_string()
+        } else {
+            self.first_day.format("%-d %b").to_string()
+        };
+        format!(
+            "Week {}, {}\u{2013}{}",
+            self.week.week(),
+            first,
+            self.last_day.format("%-d %b")
+        )
+    }
+}
+
+#[derive(Template)]
+#[template(path = "weekly.html")]
+struct WeeklyTemplate {
+    month_label: String,
+    prev_year: i32,
+    prev_month: u32,
+    next_year: i32,
+    next_month: u32,
+    category_names: Vec<String>,
+    weeks: Vec<WeekRow>,
+    // Per category, in the same order as `category_names`
+    category_totals: Vec<i64>,
+    total_spent: i64,
+}
+
+// The ISO week a transaction was made in, going by its date in the display
+// timezone `tz`.
+pub fn transaction_week(transaction: &Transaction, tz: Tz) -> Option<IsoWeek> {
+    DateTime::parse_from_rfc3339(&transaction.date)
+        .ok()
+        .map(|date| date.with_timezone(&tz).date_naive().iso_week())
+}
+
+// The ISO weeks overlapping the days from `start` up to `end`, each with its
+// first and last day clamped to that range.
+fn period_weeks(start: NaiveDate, end: NaiveDate) -> Vec<(IsoWeek, NaiveDate, NaiveDate)> {
+    let mut weeks: Vec<(IsoWeek, NaiveDate, NaiveDate)> = Vec::new();
+    for date in start.iter_days().take_while(|date| *date < end) {
+        match weeks.last_mut() {
+            Some((week, _, last_day)) if *week == date.iso_week() => *last_day = date,
+            _ => weeks.push((date.iso_week(), date, date)),
+        }
+    }
+    weeks
+}
+
+// `/weekly`: the month's spending by ISO week, in total and per category.
+pub async fn weekly_page(
+    query: web::Query<BudgetQuery>,
+    up: web::Data<UpClient>,
+    cache: web::Data<TransactionCache>,
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    (categories, rules): (web::Data<BudgetConfig>, web::Data<Rules>),
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let (year, month, mut transactions) = selected_transactions(&query, sources).await?;
+    exclude_transfers(&mut transactions, false);
+
+    let (prev_year, prev_month) = previous_month(year, month);
+    let (next_year, next_month) = next_month(year, month);
+    let budget = categorize(
+        &transactions,
+        &categories.resolve_allocations(year, month),
+        &rules,
+    );
+
+    let start_day = config.fiscal_start_day;
+    let mut weeks: Vec<WeekRow> = period_weeks(
+        period_start(start_day, year, month),
+        period_start(start_day, next_year, next_month),
+    )
+    .into_iter()
+    .map(|(week, first_day, last_day)| WeekRow {
+        week,
+        first_day,
+        last_day,
+        spent: vec![0; budget.len()],
+        total: 0,
+    })
+    .collect();
+    for (index, category) in budget.iter().enumerate() {
+        for transaction in &category.transactions {
+            let amount = transaction.base_amount();
+            if amount >= 0 {
+                continue;
+            }
+            let Some(week) = transaction_week(transaction, config.display_tz) else {
+                continue;
+            };
+            if let Some(row) = weeks.iter_mut().find(|row| row.week == week) {
+                row.spent[index] += amount.abs();
+                row.total += amount.abs();
+            }
+        }
+    }
+
+    let category_totals: Vec<i64> = (0..budget.len())
+        .map(|index| weeks.iter().map(|row| row.spent[index]).sum())
+        .collect();
+    let total_spent = weeks.iter().map(|row| row.total).sum();
+
+    Ok(render_template(
+        &WeeklyTemplate {
+            month_label: NaiveDate::from_ymd_opt(year, month, 1)
+                .map(|date| date.format("%B %Y").to_string())
+                .unwrap_or_else(|| format!("{}/{}", month, year)),
+            prev_year,
+            prev_month,
+            next_year,
+            next_month,
+            category_names: budget.into_iter().map(|category| category.name).collect(),
+            weeks,
+            category_totals,
+            total_spent,
+        },
+        &config,
+    ))
+}
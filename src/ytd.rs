@@ -0,0 +1,148 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, Error, HttpResponse};
+use askama::Template;
+use chrono::NaiveDate;
+
+use crate::cache::{CacheKey, TransactionCache};
+use crate::config::{BudgetConfig, Config, CurrencyRates, Rules};
+use crate::db::Database;
+use crate::money::filters;
+use crate::up::UpClient;
+use crate::{
+    apply_income_allocations, categorize, current_period, error_page, exclude_held,
+    fetch_transactions, filter_by_account, query_flag, render_template, selected_account,
+    selected_month, upstream_error, BudgetCategory, BudgetQuery, Sources,
+};
+
+// One row of the month-by-month table, with spending in the same order as
+// the template's category columns.
+struct MonthRow {
+    label: String,
+    // In minor units of the base currency
+    spent: Vec<i64>,
+    total: i64,
+}
+
+#[derive(Template)]
+#[template(path = "ytd.html")]
+struct YtdTemplate {
+    year: i32,
+    months: usize,
+    categories: Vec<BudgetCategory>,
+    rows: Vec<MonthRow>,
+}
+
+// Year-to-date view: every month from January up to the current one (or the
+// whole year for past years), with cumulative and per-month spending.
+pub async fn ytd_page(
+    query: web::Query<BudgetQuery>,
+    (up, cache): (web::Data<UpClient>, web::Data<TransactionCache>),
+    rates: web::Data<CurrencyRates>,
+    db: web::Data<Database>,
+    config: web::Data<Config>,
+    categories: web::Data<BudgetConfig>,
+    rules: web::Data<Rules>,
+) -> Result<HttpResponse, Error> {
+    let sources = Sources {
+        up: &up,
+        cache: &cache,
+        db: &db,
+        rates: &rates,
+        config: &config,
+        rules: &rules,
+    };
+    let year = match selected_month(&query, config.fiscal_start_day) {
+        Ok((year, _)) => year,
+        Err(message) => {
+            return Ok(error_page(
+                StatusCode::BAD_REQUEST,
+                "Invalid Year",
+                &message,
+            ))
+        }
+    };
+
+    let (current_year, current_month) = current_period(config.fiscal_start_day);
+    let last_month = match year.cmp(&current_year) {
+        std::cmp::Ordering::Less => 12,
+        std::cmp::Ordering::Equal => current_month,
+        std::cmp::Ordering::Greater => {
+            return Ok(error_page(
+                StatusCode::BAD_REQUEST,
+                "Invalid Year",
+                &format!("{} hasn't started yet.", year),
+            ))
+        }
+    };
+
+    let account_id = selected_account(&query.account_id);
+    let include_held = query_flag(&query.include_held);
+
+    // Months are fetched one after another so a cold cache doesn't fire a
+    // year's worth of requests at Up Bank at once
+    let mut monthly = Vec::new();
+    let mut all_transactions = Vec::new();
+    for month in 1..=last_month {
+        let key = CacheKey {
+            account_id: None,
+            year,
+            month,
+        };
+        let mut transactions = fetch_transactions(sources, key, query_flag(&query.refresh))
+            .await
+            .map_err(upstream_error)?;
+        filter_by_account(&mut transactions, account_id);
+        exclude_held(&mut transactions, include_held);
+
+        let month_categories = categories.resolve_allocations(year, month);
+        monthly.push((month, categorize(&transactions, &month_categories, &rules)));
+        all_transactions.extend(transactions);
+    }
+
+    let mut cumulative = categorize(&all_transactions, categories.categories(), &rules);
+    // Fixed allocations are monthly, so the year-to-date budget is the sum of
+    // each month's (possibly overridden) allocation; percentages apply to the
+    // year's income
+    for category in &mut cumulative {
+        category.allocated_amount = monthly
+            .iter()
+            .filter_map(|(_, month_categories)| {
+                month_categories.iter().find(|c| c.name == category.name)
+            })
+            .map(|c| c.allocated_amount)
+            .sum();
+    }
+    apply_income_allocations(&mut cumulative);
+
+    let rows = monthly
+        .into_iter()
+        .map(|(month, month_categories)| {
+            let spent: Vec<i64> = cumulative
+                .iter()
+                .map(|category| {
+                    month_categories
+                        .iter()
+                        .find(|c| c.name == category.name)
+                        .map_or(0, |c| c.spent_amount)
+                })
+                .collect();
+            MonthRow {
+                label: NaiveDate::from_ymd_opt(year, month, 1)
+                    .map(|date| date.format("%B").to_string())
+                    .unwrap_or_else(|| month.to_string()),
+                total: spent.iter().sum(),
+                spent,
+            }
+        })
+        .collect();
+
+    Ok(render_template(
+        &YtdTemplate {
+            year,
+            months: last_month as usize,
+            categories: cumulative,
+            rows,
+        },
+        &config,
+    ))
+}